@@ -1,18 +1,15 @@
-use actix_service::{Service, Transform};
 use actix_web::{
-    App, Error, HttpResponse, HttpServer, Responder,
-    dev::ServiceRequest,
-    get, post,
+    App, HttpResponse, HttpServer, Responder, get, post,
     web::{self, Data, Json},
 };
-use fark::{AuthError, AuthInput, Fark, Identity};
+use fark::actix::FarkAuth;
+use fark::{
+    ApiKeyRecord, ApiKeys, AuthContext, AuthError, AuthInput, Fark, Identity, InMemoryApiKeyStore,
+    Secret,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{
-    collections::HashMap,
-    future::{Ready, ready},
-    pin::Pin,
-};
+use std::collections::HashMap;
 
 #[derive(Deserialize)]
 struct UserRequest {
@@ -25,76 +22,11 @@ struct AuthSuccessResponse {
     token: String,
     message: String,
 }
-struct AuthMiddleware;
-
-pub struct AuthMiddlewareService<S> {
-    service: S,
-}
-
-impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
-where
-    S: Service<ServiceRequest, Response = actix_web::dev::ServiceResponse<B>, Error = Error>,
-    S::Future: 'static,
-    B: 'static,
-{
-    type Response = actix_web::dev::ServiceResponse<B>;
-
-    type Future = Ready<Result<Self::Transform, Self::InitError>>;
-    type Error = Error;
-    type Transform = AuthMiddlewareService<S>;
-    type InitError = ();
-
-    fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(AuthMiddlewareService { service }))
-    }
-}
-
-impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
-where
-    S: Service<ServiceRequest, Response = actix_web::dev::ServiceResponse<B>, Error = Error>,
-    S::Future: 'static,
-    B: 'static,
-{
-    type Response = actix_web::dev::ServiceResponse<B>;
-    type Error = Error;
-    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
-
-    fn poll_ready(
-        &self,
-        ctx: &mut core::task::Context<'_>,
-    ) -> std::task::Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(ctx)
-    }
-
-    fn call(&self, req: ServiceRequest) -> Self::Future {
-        let fark = req.app_data::<Data<Fark>>().cloned();
-
-        let auth_header = req.headers().get("Authorization");
-        if let Some(fark) = fark {
-            if let Some(header) = auth_header {
-                if let Ok(header_str) = header.to_str() {
-                    if header_str.starts_with("Bearer ") {
-                        let token = header_str[7..].to_string();
-                        let verifyjwt = fark.verify_jwt(token.into());
-
-                        if verifyjwt.is_ok() {
-                            let fut = self.service.call(req);
-                            return Box::pin(async move { fut.await });
-                        }
-                    }
-                }
-            }
-        }
-        Box::pin(async move {
-            Err(actix_web::error::ErrorUnauthorized(
-                "Missing or invalid authorization",
-            ))
-        })
-    }
-}
-
-async fn protected_path() -> impl Responder {
-    "This is a protected route, you are authenticated! "
+async fn protected_path(identity: Identity) -> impl Responder {
+    format!(
+        "This is a protected route, you are authenticated as {}!",
+        identity.user_id
+    )
 }
 #[get("/")]
 async fn hellopath() -> impl Responder {
@@ -104,6 +36,7 @@ async fn hellopath() -> impl Responder {
 #[post("/user")]
 async fn user(
     payload: Json<UserRequest>,
+    ctx: AuthContext,
     fark: web::Data<Fark>,
 ) -> Result<HttpResponse, AuthError> {
     let mut input = HashMap::new();
@@ -111,7 +44,7 @@ async fn user(
     input.insert("password".to_string(), payload.password.clone());
 
     let identity = fark
-        .authenticate("local", AuthInput::Local { data: input })
+        .authenticate_with_context("local", AuthInput::Local { data: input }, ctx)
         .await?;
 
     // Issue JWT with 1-hour TTL
@@ -127,28 +60,42 @@ async fn user(
 async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("debug"));
     // Configure Fark once and share as app data
-    let mut fark = Fark::new().with_local(|data: HashMap<String, String>| async move {
-        if data.get("username") == Some(&"user".to_string())
-            && data.get("password") == Some(&"pass".to_string())
-        {
-            Ok(Identity {
-                user_id: "123".to_string(),
-                data: json!({ "role": "user" }),
-            })
-        } else {
-            Err(AuthError::InvalidInput)
-        }
+    let fark = Fark::new()
+        .with_local(|data: HashMap<String, String>| async move {
+            if data.get("username") == Some(&"user".to_string())
+                && data.get("password") == Some(&"pass".to_string())
+            {
+                Ok(Identity::new("123", json!({ "role": "user" })))
+            } else {
+                Err(AuthError::InvalidInput)
+            }
+        })
+        .with_jwt(Secret::from_env("JWT_SECRET").expect("JWT_SECRET is set"))
+        .expect("JWT_SECRET is a strong key");
+
+    // Machine clients send `X-Api-Key: fark_...` instead of a bearer token
+    let api_keys = ApiKeys::new(InMemoryApiKeyStore::new());
+    let issued = api_keys
+        .issue("service-1", ["read"], None)
+        .await
+        .map_err(std::io::Error::other)?;
+    // Hand `issued.key` to the client over a secure channel; only its id is safe to log
+    log::info!("Issued demo API key {}", issued.record.id);
+    let fark = fark.with_api_keys(api_keys, |record: ApiKeyRecord| async move {
+        Ok(Identity::new(
+            record.user_id,
+            json!({ "scopes": record.scopes }),
+        ))
     });
-    fark.with_jwt("your-secure-secret-key-here".to_string()); 
 
-    let fark_data = Data::new(fark);
+    let fark_data = Data::new(fark.clone());
     println!("Server started on http://0.0.0.0:3000");
     HttpServer::new(move || {
         App::new()
             .app_data(fark_data.clone())
             .service(
                 web::scope("/api")
-                    .wrap(AuthMiddleware)
+                    .wrap(FarkAuth::new(fark.clone()))
                     .route("/protected", web::get().to(protected_path)),
             )
             .service(hellopath)
@@ -0,0 +1,439 @@
+//! Actix-web adapters, enabled by the `actix` feature.
+
+use crate::context::AuthContext;
+use crate::error::AuthError;
+use crate::fark::{Fark, read};
+use crate::http_auth::{DigestAuth, DigestCredentials, basic_challenge, parse_basic};
+use crate::identity::Identity;
+use crate::input::AuthInput;
+use crate::introspection::TokenHintRequest;
+use crate::oauth_server::{AuthorizationRequest, AuthorizationServer, OAuthError, TokenRequest};
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    AUTHORIZATION, CACHE_CONTROL, HeaderValue, LOCATION, USER_AGENT, WWW_AUTHENTICATE,
+};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError, web};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const TENANT_HEADER: &str = "x-tenant-id";
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Fills the context from the peer address and the `User-Agent`, `X-Request-Id` and
+/// `X-Tenant-Id` headers. `X-Forwarded-For` is ignored because clients can forge it;
+/// behind a trusted proxy, set `client_ip` from the proxy's header yourself.
+impl From<&HttpRequest> for AuthContext {
+    fn from(req: &HttpRequest) -> Self {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        AuthContext {
+            client_ip: req.peer_addr().map(|addr| addr.ip()),
+            user_agent: header(USER_AGENT.as_str()),
+            tenant: header(TENANT_HEADER),
+            request_id: header(REQUEST_ID_HEADER),
+        }
+    }
+}
+
+impl FromRequest for AuthContext {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(AuthContext::from(req)))
+    }
+}
+
+/// Credentials of an `Authorization: {scheme} {credentials}` header. The scheme is
+/// matched case-insensitively.
+pub fn authorization<'a>(req: &'a HttpRequest, scheme: &str) -> Option<&'a str> {
+    let (found, credentials) = req
+        .headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .split_once(' ')?;
+    found
+        .eq_ignore_ascii_case(scheme)
+        .then(|| credentials.trim())
+        .filter(|credentials| !credentials.is_empty())
+}
+
+/// The token of an `Authorization: Bearer` header.
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    authorization(req, "Bearer").map(str::to_string)
+}
+
+/// The API key of an `X-Api-Key` or `Authorization: ApiKey` header.
+pub fn api_key(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| authorization(req, "ApiKey"))
+        .map(str::to_string)
+}
+
+/// Middleware that requires credentials on every request and makes the resulting
+/// [`Identity`] available to handlers as an extractor. Accepted are a bearer token
+/// (checked with [`Fark::verify_active_jwt`]; tokens of other issuers go to the
+/// `"introspection"` strategy if registered), an API key (the `"api_key"` strategy) and,
+/// once enabled, Basic (the `"local"` strategy) and Digest (the `"digest"` strategy)
+/// credentials.
+///
+/// Failures are answered with a problem document; 401s carry a `WWW-Authenticate`
+/// challenge for every accepted scheme.
+#[derive(Clone)]
+pub struct FarkAuth {
+    fark: Fark,
+    basic_realm: Option<String>,
+    digest: Option<DigestAuth>,
+}
+
+impl FarkAuth {
+    pub fn new(fark: Fark) -> Self {
+        Self {
+            fark,
+            basic_realm: None,
+            digest: None,
+        }
+    }
+
+    /// Accepts `Authorization: Basic` and passes it to the `"local"` strategy.
+    pub fn basic(mut self, realm: impl Into<String>) -> Self {
+        self.basic_realm = Some(realm.into());
+        self
+    }
+
+    /// Accepts `Authorization: Digest`. Register the same `digest` with
+    /// [`Fark::with_digest`].
+    pub fn digest(mut self, digest: DigestAuth) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    async fn authenticate(&self, req: &HttpRequest) -> Result<(Identity, AuthScheme), AuthError> {
+        if let Some(token) = bearer_token(req) {
+            // Our own tokens are only ever checked here, so an expired or revoked one
+            // cannot be revived by another issuer's introspection endpoint.
+            if self.fark.has_strategy("introspection") && !claims_local_issuer(&self.fark, &token) {
+                let input = AuthInput::BearerToken { token };
+                return self
+                    .fark
+                    .authenticate_with_context("introspection", input, req.into())
+                    .await
+                    .map(|identity| (identity, AuthScheme::Bearer));
+            }
+            return self
+                .fark
+                .verify_active_jwt(token)
+                .await
+                .map(|identity| (identity, AuthScheme::Bearer));
+        }
+        if let Some(key) = api_key(req) {
+            return self
+                .fark
+                .authenticate_with_context("api_key", AuthInput::ApiKey { key }, req.into())
+                .await
+                .map(|identity| (identity, AuthScheme::ApiKey));
+        }
+        let header = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if self.basic_realm.is_some() && authorization(req, "Basic").is_some() {
+            return self
+                .fark
+                .authenticate_with_context("local", parse_basic(header)?, req.into())
+                .await
+                .map(|identity| (identity, AuthScheme::Basic))
+                .map_err(unauthorized);
+        }
+        if self.digest.is_some() && authorization(req, "Digest").is_some() {
+            let credentials = DigestCredentials::parse(header)?;
+            let uri = req.uri().path_and_query().map(|uri| uri.as_str());
+            if Some(credentials.uri.as_str()) != uri {
+                return Err(AuthError::InvalidInput);
+            }
+            let input = AuthInput::Digest {
+                credentials,
+                method: req.method().to_string(),
+            };
+            return self
+                .fark
+                .authenticate_with_context("digest", input, req.into())
+                .await
+                .map(|identity| (identity, AuthScheme::Digest))
+                .map_err(unauthorized);
+        }
+        Err(AuthError::InvalidToken)
+    }
+
+    /// The error response, with `WWW-Authenticate` challenges on a 401.
+    fn reject(&self, req: &HttpRequest, err: AuthError) -> Error {
+        let mut response = err.error_response();
+        if response.status().as_u16() == 401 {
+            let mut challenges = vec!["Bearer".to_string()];
+            challenges.extend(self.basic_realm.as_deref().map(basic_challenge));
+            if let Some(digest) = &self.digest {
+                let stale = authorization(req, "Digest").is_some()
+                    && matches!(err.cause(), AuthError::InvalidToken);
+                challenges.extend(digest.challenge(stale).ok());
+            }
+            for challenge in challenges {
+                if let Ok(value) = HeaderValue::from_str(&challenge) {
+                    response.headers_mut().append(WWW_AUTHENTICATE, value);
+                }
+            }
+        }
+        InternalError::from_response(err, response).into()
+    }
+}
+
+/// Whether `token` claims to be one of `fark`'s: a JWT whose `iss`, read without
+/// checking the signature, is the configured `jwt.issuer` (or absent when none is
+/// set). Only used to route a token to the right verifier, never to trust it.
+fn claims_local_issuer(fark: &Fark, token: &str) -> bool {
+    let mut segments = token.split('.');
+    let (Some(_), Some(payload), Some(_), None) = (
+        segments.next(),
+        segments.next(),
+        segments.next(),
+        segments.next(),
+    ) else {
+        return false;
+    };
+    let Some(claims) = URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|payload| serde_json::from_slice::<Value>(&payload).ok())
+    else {
+        return false;
+    };
+    claims.get("iss").and_then(Value::as_str) == read(&fark.jwt).issuer.as_deref()
+}
+
+/// Basic and Digest clients only prompt again on a 401 with a challenge, so every
+/// credential error on those schemes is answered as a failed authentication.
+fn unauthorized(err: AuthError) -> AuthError {
+    match err {
+        AuthError::AuthenticationFailed { .. } => err,
+        err if err.is_credential_error() => AuthError::AuthenticationFailed {
+            cause: Box::new(err),
+        },
+        err => err,
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for FarkAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = FarkAuthService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(FarkAuthService {
+            service: Rc::new(service),
+            auth: Rc::new(self.clone()),
+        }))
+    }
+}
+
+pub struct FarkAuthService<S> {
+    service: Rc<S>,
+    auth: Rc<FarkAuth>,
+}
+
+impl<S, B> Service<ServiceRequest> for FarkAuthService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let auth = self.auth.clone();
+        Box::pin(async move {
+            let (identity, scheme) = auth
+                .authenticate(req.request())
+                .await
+                .map_err(|err| auth.reject(req.request(), err))?;
+            req.extensions_mut().insert(identity);
+            req.extensions_mut().insert(scheme);
+            service.call(req).await
+        })
+    }
+}
+
+/// How [`FarkAuth`] authenticated the request. Available as an extractor on the
+/// routes it wraps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// A bearer token, verified locally or by the `"introspection"` strategy.
+    Bearer,
+    /// An `X-Api-Key` or `Authorization: ApiKey` header.
+    ApiKey,
+    Basic,
+    Digest,
+}
+
+impl FromRequest for AuthScheme {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<AuthScheme>()
+                .copied()
+                .ok_or(AuthError::InvalidToken),
+        )
+    }
+}
+
+/// The identity authenticated by [`FarkAuth`]. Fails with 401 on routes the middleware
+/// does not wrap.
+impl FromRequest for Identity {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Identity>()
+                .cloned()
+                .ok_or(AuthError::InvalidToken),
+        )
+    }
+}
+
+impl ResponseError for OAuthError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        response.insert_header((CACHE_CONTROL, "no-store"));
+        if matches!(self, OAuthError::InvalidClient) {
+            response.insert_header((WWW_AUTHENTICATE, "Basic"));
+        }
+        response.json(self.body())
+    }
+}
+
+/// Authorization endpoint, for `GET /authorize`. Mount it behind [`FarkAuth`] or
+/// another way to sign the user in first; the server's consent hook runs here.
+pub async fn oauth_authorize(
+    server: web::Data<AuthorizationServer>,
+    identity: Identity,
+    request: web::Query<AuthorizationRequest>,
+) -> Result<HttpResponse, OAuthError> {
+    let location = server.authorize(&request, identity).await?;
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, location))
+        .finish())
+}
+
+/// Token endpoint, for `POST /token` with a form body. Client credentials are taken
+/// from the form or an `Authorization: Basic` header.
+pub async fn oauth_token(
+    server: web::Data<AuthorizationServer>,
+    req: HttpRequest,
+    form: web::Form<TokenRequest>,
+) -> Result<HttpResponse, OAuthError> {
+    let mut request = form.into_inner();
+    if let Some(header) = basic_header(&req) {
+        request = request.basic_credentials(header)?;
+    }
+    let tokens = server.token(request).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(tokens))
+}
+
+/// Revocation endpoint (RFC 7009), for `POST /revoke` with a form body. Client
+/// credentials are taken from the form or an `Authorization: Basic` header.
+pub async fn oauth_revoke(
+    server: web::Data<AuthorizationServer>,
+    req: HttpRequest,
+    form: web::Form<TokenHintRequest>,
+) -> Result<HttpResponse, OAuthError> {
+    let mut request = form.into_inner();
+    if let Some(header) = basic_header(&req) {
+        request = request.basic_credentials(header)?;
+    }
+    server.revoke(&request).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Introspection endpoint (RFC 7662), for `POST /introspect` with a form body.
+/// RFC 7662 section 2.1 only lets protected resources introspect, so mount it behind
+/// [`FarkAuth`] and give each resource server an API key with the `introspect` scope.
+/// Any other caller, such as an end user's key or bearer token, gets a 403.
+pub async fn introspect(
+    fark: web::Data<Fark>,
+    scheme: AuthScheme,
+    identity: Identity,
+    form: web::Form<TokenHintRequest>,
+) -> Result<HttpResponse, AuthError> {
+    if scheme != AuthScheme::ApiKey || !identity.has_scope("introspect") {
+        return Err(AuthError::InsufficientScope);
+    }
+    let response = fark.introspect(&form.token).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(response))
+}
+
+/// OpenID Connect discovery, for `GET /.well-known/openid-configuration`.
+pub async fn oidc_discovery(fark: web::Data<Fark>) -> Result<HttpResponse, AuthError> {
+    Ok(HttpResponse::Ok().json(fark.oidc_discovery()?))
+}
+
+/// The signing keys, for `GET /jwks`.
+pub async fn oidc_jwks(fark: web::Data<Fark>) -> Result<HttpResponse, AuthError> {
+    Ok(HttpResponse::Ok().json(fark.jwks()?))
+}
+
+/// OpenID Connect userinfo, for `GET /userinfo`. It takes the bearer access token
+/// the [`AuthorizationServer`] issued to the client, so do not mount it behind
+/// [`FarkAuth`], which only accepts first-party tokens.
+pub async fn oidc_userinfo(
+    fark: web::Data<Fark>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    let token = bearer_token(&req).ok_or(AuthError::InvalidToken)?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(fark.userinfo(token).await?))
+}
+
+fn basic_header(req: &HttpRequest) -> Option<&str> {
+    authorization(req, "Basic")?;
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+}
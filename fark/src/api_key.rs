@@ -0,0 +1,238 @@
+use crate::crypto::{base32_encode, hex, random_bytes};
+use crate::error::AuthError;
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+
+/// What is stored for an API key. The key itself is never kept, only its SHA-256.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyRecord {
+    /// Public identifier, embedded in the key and safe to log or show in a UI.
+    pub id: String,
+    pub user_id: String,
+    pub hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+    pub revoked: bool,
+}
+
+impl ApiKeyRecord {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+/// A newly issued key. Show `key` to its owner once; only `record` is persisted.
+#[derive(Debug, Clone)]
+pub struct IssuedApiKey {
+    pub key: String,
+    pub record: ApiKeyRecord,
+}
+
+/// Holds [`ApiKeyRecord`]s by id.
+pub trait ApiKeyStore: Send + Sync {
+    fn insert(&self, record: ApiKeyRecord) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    fn get(&self, id: &str) -> BoxFuture<'_, Result<Option<ApiKeyRecord>, AuthError>>;
+
+    /// Sets `last_used_at`. Called on every successful verification, so it should be
+    /// cheap; implementations may coalesce writes.
+    fn touch(&self, id: &str, used_at: u64) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    /// Marks the key revoked, returning whether it existed.
+    fn revoke(&self, id: &str) -> BoxFuture<'_, Result<bool, AuthError>>;
+
+    fn list(&self, user_id: &str) -> BoxFuture<'_, Result<Vec<ApiKeyRecord>, AuthError>>;
+}
+
+/// Process-local [`ApiKeyStore`], for tests and single-instance deployments.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryApiKeyStore {
+    records: Arc<Mutex<HashMap<String, ApiKeyRecord>>>,
+}
+
+impl InMemoryApiKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ApiKeyStore for InMemoryApiKeyStore {
+    fn insert(&self, record: ApiKeyRecord) -> BoxFuture<'_, Result<(), AuthError>> {
+        let inserted = self
+            .records
+            .lock()
+            .map(|mut records| {
+                records.insert(record.id.clone(), record);
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { inserted })
+    }
+
+    fn get(&self, id: &str) -> BoxFuture<'_, Result<Option<ApiKeyRecord>, AuthError>> {
+        let record = self
+            .records
+            .lock()
+            .map(|records| records.get(id).cloned())
+            .map_err(AuthError::from);
+        Box::pin(async move { record })
+    }
+
+    fn touch(&self, id: &str, used_at: u64) -> BoxFuture<'_, Result<(), AuthError>> {
+        let touched = self
+            .records
+            .lock()
+            .map(|mut records| {
+                if let Some(record) = records.get_mut(id) {
+                    record.last_used_at = Some(used_at);
+                }
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { touched })
+    }
+
+    fn revoke(&self, id: &str) -> BoxFuture<'_, Result<bool, AuthError>> {
+        let revoked = self
+            .records
+            .lock()
+            .map(|mut records| {
+                records
+                    .get_mut(id)
+                    .map(|record| record.revoked = true)
+                    .is_some()
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { revoked })
+    }
+
+    fn list(&self, user_id: &str) -> BoxFuture<'_, Result<Vec<ApiKeyRecord>, AuthError>> {
+        let listed = self
+            .records
+            .lock()
+            .map(|records| {
+                let mut listed: Vec<ApiKeyRecord> = records
+                    .values()
+                    .filter(|record| record.user_id == user_id)
+                    .cloned()
+                    .collect();
+                listed.sort_by_key(|record| record.created_at);
+                listed
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { listed })
+    }
+}
+
+/// Long-lived keys for machine-to-machine clients, of the form
+/// `{prefix}_{id}_{secret}` (e.g. `fark_3kq9x2mfa7pd_...`).
+///
+/// The prefix makes leaked keys easy to spot with secret scanners, the id finds the
+/// stored record without a table scan, and the secret carries 256 random bits.
+#[derive(Clone)]
+pub struct ApiKeys {
+    store: Arc<dyn ApiKeyStore>,
+    prefix: String,
+}
+
+impl ApiKeys {
+    pub fn new<S>(store: S) -> Self
+    where
+        S: ApiKeyStore + 'static,
+    {
+        Self {
+            store: Arc::new(store),
+            prefix: "fark".to_string(),
+        }
+    }
+
+    /// Prefix of issued keys. Defaults to `fark`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Issues a key for `user_id`. `ttl_secs` of `None` never expires.
+    pub async fn issue<I, S>(
+        &self,
+        user_id: impl Into<String>,
+        scopes: I,
+        ttl_secs: Option<u64>,
+    ) -> Result<IssuedApiKey, AuthError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let created_at = now()?;
+        let id = base32_encode(&random_bytes(8))[..12].to_ascii_lowercase();
+        let secret = base32_encode(&random_bytes(32)).to_ascii_lowercase();
+        let key = format!("{}_{id}_{secret}", self.prefix);
+        let record = ApiKeyRecord {
+            id,
+            user_id: user_id.into(),
+            hash: hash_api_key(&key),
+            scopes: scopes.into_iter().map(Into::into).collect(),
+            created_at,
+            expires_at: ttl_secs
+                .map(|ttl| {
+                    created_at
+                        .checked_add(ttl)
+                        .ok_or_else(|| AuthError::internal("api key ttl_secs is out of range"))
+                })
+                .transpose()?,
+            last_used_at: None,
+            revoked: false,
+        };
+        self.store.insert(record.clone()).await?;
+        Ok(IssuedApiKey { key, record })
+    }
+
+    /// Checks `key` and records its use. Unknown, revoked, expired and mismatching
+    /// keys all fail with [`AuthError::CredentialRejected`].
+    pub async fn verify(&self, key: &str) -> Result<ApiKeyRecord, AuthError> {
+        let id = key
+            .strip_prefix(self.prefix.as_str())
+            .and_then(|rest| rest.strip_prefix('_'))
+            .and_then(|rest| rest.split_once('_'))
+            .map(|(id, _)| id)
+            .ok_or(AuthError::InvalidInput)?;
+
+        let mut record = self
+            .store
+            .get(id)
+            .await?
+            .ok_or(AuthError::CredentialRejected)?;
+        let current = now()?;
+        if !bool::from(hash_api_key(key).as_bytes().ct_eq(record.hash.as_bytes()))
+            || record.revoked
+            || record
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= current)
+        {
+            return Err(AuthError::CredentialRejected);
+        }
+
+        self.store.touch(&record.id, current).await?;
+        record.last_used_at = Some(current);
+        Ok(record)
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<bool, AuthError> {
+        self.store.revoke(id).await
+    }
+
+    /// Keys of `user_id`, oldest first, including revoked and expired ones.
+    pub async fn list(&self, user_id: &str) -> Result<Vec<ApiKeyRecord>, AuthError> {
+        self.store.list(user_id).await
+    }
+}
+
+/// Hashes an API key for storage. Keys carry 256 random bits, so a fast hash is
+/// enough; there is nothing to brute-force.
+pub fn hash_api_key(key: &str) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
@@ -0,0 +1,139 @@
+//! Minimal CBOR (RFC 8949) decoder covering what WebAuthn attestation objects and
+//! COSE keys use: integers, byte/text strings, arrays, maps and simple values.
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Cbor {
+    Int(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Bool(bool),
+    Null,
+}
+
+impl Cbor {
+    pub(crate) fn get(&self, key: &Cbor) -> Option<&Cbor> {
+        match self {
+            Cbor::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_text(&self, key: &str) -> Option<&Cbor> {
+        self.get(&Cbor::Text(key.to_string()))
+    }
+
+    pub(crate) fn get_int(&self, key: i128) -> Option<&Cbor> {
+        self.get(&Cbor::Int(key))
+    }
+
+    pub(crate) fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Cbor::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_text(&self) -> Option<&str> {
+        match self {
+            Cbor::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_int(&self) -> Option<i128> {
+        match self {
+            Cbor::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes one item from the start of `input`, returning it and the bytes consumed.
+pub(crate) fn decode(input: &[u8]) -> Option<(Cbor, usize)> {
+    let mut reader = Reader { input, pos: 0 };
+    let value = reader.item(0)?;
+    Some((value, reader.pos))
+}
+
+const MAX_DEPTH: usize = 16;
+
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let end = self.pos.checked_add(len)?;
+        let slice = self.input.get(self.pos..end)?;
+        self.pos = end;
+        Some(slice)
+    }
+
+    fn argument(&mut self, info: u8) -> Option<u64> {
+        match info {
+            0..=23 => Some(info as u64),
+            24 => Some(self.take(1)?[0] as u64),
+            25 => Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?) as u64),
+            26 => Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?) as u64),
+            27 => Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?)),
+            // Indefinite lengths are not used by authenticators
+            _ => None,
+        }
+    }
+
+    fn item(&mut self, depth: usize) -> Option<Cbor> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let info = initial & 0x1f;
+
+        match major {
+            0 => Some(Cbor::Int(self.argument(info)? as i128)),
+            1 => Some(Cbor::Int(-1 - self.argument(info)? as i128)),
+            2 => {
+                let len = self.argument(info)? as usize;
+                Some(Cbor::Bytes(self.take(len)?.to_vec()))
+            }
+            3 => {
+                let len = self.argument(info)? as usize;
+                let text = std::str::from_utf8(self.take(len)?).ok()?;
+                Some(Cbor::Text(text.to_string()))
+            }
+            4 => {
+                let len = self.argument(info)? as usize;
+                let mut items = Vec::with_capacity(len.min(64));
+                for _ in 0..len {
+                    items.push(self.item(depth + 1)?);
+                }
+                Some(Cbor::Array(items))
+            }
+            5 => {
+                let len = self.argument(info)? as usize;
+                let mut entries = Vec::with_capacity(len.min(64));
+                for _ in 0..len {
+                    let key = self.item(depth + 1)?;
+                    let value = self.item(depth + 1)?;
+                    entries.push((key, value));
+                }
+                Some(Cbor::Map(entries))
+            }
+            // Tags: decode and return the tagged item
+            6 => {
+                self.argument(info)?;
+                self.item(depth + 1)
+            }
+            7 => match info {
+                20 => Some(Cbor::Bool(false)),
+                21 => Some(Cbor::Bool(true)),
+                22 | 23 => Some(Cbor::Null),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
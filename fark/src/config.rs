@@ -0,0 +1,431 @@
+use crate::fark::{Fark, read, write};
+use crate::jwt::JwtSettings;
+use crate::secret::Secret;
+use crate::throttle::{InMemoryThrottleStore, Throttle, ThrottlePolicy};
+use jsonwebtoken::Algorithm;
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Errors raised while loading or validating a [`FarkConfig`].
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid {format} syntax: {message}")]
+    Syntax {
+        format: &'static str,
+        message: String,
+    },
+    #[error("unsupported config format {0:?} (is the matching feature enabled?)")]
+    UnsupportedFormat(String),
+    /// `field` is the dotted path of the offending setting, e.g. `jwt.ttl_secs`.
+    #[error("invalid value at `{field}`: {message}")]
+    InvalidField { field: String, message: String },
+}
+
+impl ConfigError {
+    pub(crate) fn field(field: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigError::InvalidField {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Everything needed to build a [`Fark`] without code: token signing, enabled
+/// strategies, OAuth provider credentials and rate limits.
+///
+/// Load it from a file, the environment or both, then pass it to [`Fark::from_config`]
+/// and register strategy closures on the result.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FarkConfig {
+    pub jwt: Option<JwtConfig>,
+    /// Strategy names `authenticate` accepts. Empty allows every registered strategy.
+    pub strategies: Vec<String>,
+    pub oauth: HashMap<String, OAuthProviderConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    #[serde(default = "default_algorithm")]
+    pub algorithm: Algorithm,
+    /// HMAC key, required for the `HS*` algorithms. Must be at least as long as the
+    /// hash output (32 bytes for HS256) and randomly generated.
+    #[serde(default)]
+    pub secret: Option<Secret>,
+    /// File holding the HMAC key, used when `secret` is not set.
+    #[serde(default)]
+    pub secret_file: Option<PathBuf>,
+    /// PEM private key used to sign with the RSA, ECDSA and EdDSA algorithms.
+    #[serde(default)]
+    pub private_key_pem: Option<Secret>,
+    /// File holding the PEM private key, used when `private_key_pem` is not set.
+    #[serde(default)]
+    pub private_key_file: Option<PathBuf>,
+    /// PEM public key used to verify with the RSA, ECDSA and EdDSA algorithms.
+    #[serde(default)]
+    pub public_key_pem: Option<String>,
+    #[serde(default = "default_ttl", deserialize_with = "number")]
+    pub ttl_secs: u64,
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default)]
+    pub audience: Option<String>,
+    #[serde(default = "default_leeway", deserialize_with = "number")]
+    pub leeway_secs: u64,
+}
+
+fn default_algorithm() -> Algorithm {
+    Algorithm::HS256
+}
+
+fn default_ttl() -> u64 {
+    3600
+}
+
+fn default_leeway() -> u64 {
+    30
+}
+
+/// Client credentials for an OAuth provider, looked up with [`Fark::oauth_provider`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OAuthProviderConfig {
+    pub client_id: String,
+    pub client_secret: Secret,
+    pub redirect_uri: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    #[serde(default = "ThrottlePolicy::account")]
+    pub account: ThrottlePolicy,
+    #[serde(default = "ThrottlePolicy::ip")]
+    pub ip: ThrottlePolicy,
+}
+
+impl FarkConfig {
+    pub fn from_json_str(input: &str) -> Result<Self, ConfigError> {
+        let value = serde_json::from_str(input).map_err(|err| ConfigError::Syntax {
+            format: "json",
+            message: err.to_string(),
+        })?;
+        from_value(value)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(input: &str) -> Result<Self, ConfigError> {
+        let value = toml::from_str(input).map_err(|err| ConfigError::Syntax {
+            format: "toml",
+            message: err.to_string(),
+        })?;
+        from_value(value)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_str(input: &str) -> Result<Self, ConfigError> {
+        let value = serde_yaml::from_str(input).map_err(|err| ConfigError::Syntax {
+            format: "yaml",
+            message: err.to_string(),
+        })?;
+        from_value(value)
+    }
+
+    /// Loads a `.json`, `.toml` (feature `toml`) or `.yaml`/`.yml` (feature `yaml`) file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        from_value(read_file(path.as_ref())?)
+    }
+
+    /// Reads settings from environment variables named `{prefix}_{path}`, with `__`
+    /// separating nested keys: `FARK_JWT__SECRET`, `FARK_OAUTH__GOOGLE__CLIENT_ID`.
+    /// Values starting with `[` or `{` are parsed as JSON (`["local"]`); all others stay
+    /// strings, so digit-only secrets or `null` are kept verbatim, and numeric settings
+    /// accept them as digits (`600`). Other variables are ignored, while a prefixed one
+    /// that is not valid Unicode is an error.
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_env_vars(prefix, process_env(prefix)?)
+    }
+
+    /// Like [`from_env`](Self::from_env), reading from `vars` instead of the process
+    /// environment.
+    pub fn from_env_vars(
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut value = Value::Object(Map::new());
+        merge_env(&mut value, prefix, vars);
+        from_value(value)
+    }
+
+    /// Loads `path`, then overrides it with environment variables as in
+    /// [`from_env`](Self::from_env).
+    pub fn from_file_and_env(path: impl AsRef<Path>, prefix: &str) -> Result<Self, ConfigError> {
+        let mut value = read_file(path.as_ref())?;
+        merge_env(&mut value, prefix, process_env(prefix)?);
+        from_value(value)
+    }
+
+    /// Checks settings that parse but cannot work or are unsafe, e.g. an HMAC algorithm
+    /// without a secret, a short or low-entropy HMAC secret, or an unreadable PEM key.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.resolve().map(|_| ())
+    }
+
+    /// Validates the settings and resolves the `jwt` section, reading its key files
+    /// once, so [`Fark::reload`] applies exactly what was checked.
+    fn resolve(&self) -> Result<Option<JwtSettings>, ConfigError> {
+        let jwt = self.jwt.as_ref().map(JwtConfig::resolve).transpose()?;
+        for (name, provider) in &self.oauth {
+            if provider.client_id.trim().is_empty() {
+                return Err(ConfigError::field(
+                    format!("oauth.{name}.client_id"),
+                    "must not be empty",
+                ));
+            }
+            if !provider.redirect_uri.starts_with("https://")
+                && !provider.redirect_uri.starts_with("http://")
+            {
+                return Err(ConfigError::field(
+                    format!("oauth.{name}.redirect_uri"),
+                    "must be an http(s) URL",
+                ));
+            }
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            for (key, policy) in [("account", rate_limit.account), ("ip", rate_limit.ip)] {
+                if policy.base_delay > policy.max_delay {
+                    return Err(ConfigError::field(
+                        format!("rate_limit.{key}.base_delay"),
+                        "must not exceed max_delay",
+                    ));
+                }
+            }
+        }
+        Ok(jwt)
+    }
+}
+
+impl JwtConfig {
+    /// Reads key files and checks the keys, producing the settings `Fark` signs with.
+    fn resolve(&self) -> Result<JwtSettings, ConfigError> {
+        if self.ttl_secs == 0 {
+            return Err(ConfigError::field("jwt.ttl_secs", "must be greater than 0"));
+        }
+        let secret = match (&self.secret, &self.secret_file) {
+            (Some(secret), _) => Some(secret.clone()),
+            (None, Some(path)) => Some(Secret::from_file(path)?),
+            (None, None) => None,
+        };
+        let private_key_pem = match (&self.private_key_pem, &self.private_key_file) {
+            (Some(pem), _) => Some(pem.clone()),
+            (None, Some(path)) => Some(Secret::from_file(path)?),
+            (None, None) => None,
+        };
+        let mut settings = JwtSettings {
+            algorithm: self.algorithm,
+            secret: secret.unwrap_or_default(),
+            private_key_pem,
+            public_key_pem: self.public_key_pem.clone(),
+            issuer: self.issuer.clone(),
+            audience: self.audience.clone(),
+            ttl_secs: self.ttl_secs,
+            leeway_secs: self.leeway_secs,
+            ..JwtSettings::default()
+        };
+
+        match self.algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                if settings.secret.is_empty() {
+                    return Err(ConfigError::field(
+                        "jwt.secret",
+                        format!("required for {:?}", self.algorithm),
+                    ));
+                }
+                settings
+                    .secret
+                    .check_hmac_strength(self.algorithm)
+                    .map_err(|message| ConfigError::field("jwt.secret", message))?;
+            }
+            algorithm => {
+                if settings.private_key_pem.is_none() && settings.public_key_pem.is_none() {
+                    return Err(ConfigError::field(
+                        "jwt.private_key_pem",
+                        format!("a private or public key is required for {algorithm:?}"),
+                    ));
+                }
+            }
+        }
+        settings.build_keys()?;
+        Ok(settings)
+    }
+}
+
+/// Deserializes a number that may also be given as a string of digits, as
+/// environment variables are.
+pub(crate) fn number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Number<T> {
+        Number(T),
+        Text(String),
+    }
+    match Number::deserialize(deserializer)? {
+        Number::Number(number) => Ok(number),
+        Number::Text(text) => text.trim().parse().map_err(de::Error::custom),
+    }
+}
+
+fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, ConfigError> {
+    serde_path_to_error::deserialize(value).map_err(|err| {
+        let field = err.path().to_string();
+        ConfigError::InvalidField {
+            field,
+            message: err.into_inner().to_string(),
+        }
+    })
+}
+
+fn read_file(path: &Path) -> Result<Value, ConfigError> {
+    let input = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let syntax = |format| move |message: String| ConfigError::Syntax { format, message };
+
+    match extension.as_str() {
+        "json" => serde_json::from_str(&input).map_err(|err| syntax("json")(err.to_string())),
+        #[cfg(feature = "toml")]
+        "toml" => toml::from_str(&input).map_err(|err| syntax("toml")(err.to_string())),
+        #[cfg(feature = "yaml")]
+        "yaml" | "yml" => {
+            serde_yaml::from_str(&input).map_err(|err| syntax("yaml")(err.to_string()))
+        }
+        other => Err(ConfigError::UnsupportedFormat(other.to_string())),
+    }
+}
+
+/// The process environment variables starting with `{prefix}_`. Unlike
+/// `std::env::vars`, this does not panic on variables that are not valid Unicode.
+fn process_env(prefix: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let prefix = format!("{}_", prefix.to_ascii_uppercase());
+    let mut vars = Vec::new();
+    for (key, value) in std::env::vars_os() {
+        if !key.as_encoded_bytes().starts_with(prefix.as_bytes()) {
+            continue;
+        }
+        match (key.into_string(), value.into_string()) {
+            (Ok(key), Ok(value)) => vars.push((key, value)),
+            (Ok(key), Err(_)) => return Err(ConfigError::field(key, "not valid Unicode")),
+            (Err(key), _) => {
+                return Err(ConfigError::field(
+                    key.to_string_lossy(),
+                    "not valid Unicode",
+                ));
+            }
+        }
+    }
+    Ok(vars)
+}
+
+fn merge_env(root: &mut Value, prefix: &str, vars: impl IntoIterator<Item = (String, String)>) {
+    let prefix = format!("{}_", prefix.to_ascii_uppercase());
+    for (key, raw) in vars {
+        let Some(path) = key.strip_prefix(&prefix) else {
+            continue;
+        };
+        let segments: Vec<String> = path.split("__").map(str::to_ascii_lowercase).collect();
+        let value = if raw.starts_with(['[', '{']) {
+            serde_json::from_str(&raw).unwrap_or(Value::String(raw))
+        } else {
+            Value::String(raw)
+        };
+        insert_path(root, &segments, value);
+    }
+}
+
+fn insert_path(node: &mut Value, path: &[String], value: Value) {
+    if !node.is_object() {
+        *node = Value::Object(Map::new());
+    }
+    let Value::Object(map) = node else {
+        return;
+    };
+    match path {
+        [] => {}
+        [last] => {
+            map.insert(last.clone(), value);
+        }
+        [first, rest @ ..] => {
+            insert_path(map.entry(first.clone()).or_insert(Value::Null), rest, value)
+        }
+    }
+}
+
+impl Fark {
+    /// Builds a `Fark` from validated configuration. Strategy closures are still
+    /// registered in code with `with_local`, `with_pin`, ...
+    pub fn from_config(config: FarkConfig) -> Result<Self, ConfigError> {
+        let fark = Fark::new();
+        fark.reload(config)?;
+        Ok(fark)
+    }
+
+    /// Applies new configuration to this `Fark` and all its clones without a restart.
+    /// The config is validated first and nothing changes if it is invalid.
+    ///
+    /// The strategy allow-list and OAuth providers are replaced. The `jwt` and
+    /// `rate_limit` sections replace the current settings only when present; changed
+    /// rate limits keep the attempt history recorded so far.
+    pub fn reload(&self, config: FarkConfig) -> Result<(), ConfigError> {
+        let jwt = config.resolve()?;
+
+        if let Some(jwt) = jwt {
+            *write(&self.jwt) = jwt;
+        }
+        *write(&self.enabled_strategies) =
+            (!config.strategies.is_empty()).then(|| config.strategies.into_iter().collect());
+        *write(&self.oauth_providers) = config.oauth;
+        if let Some(rate_limit) = config.rate_limit {
+            let mut throttle = write(&self.throttle);
+            let current = throttle
+                .take()
+                .unwrap_or_else(|| Throttle::new(InMemoryThrottleStore::new()));
+            *throttle = Some(
+                current
+                    .account_policy(rate_limit.account)
+                    .ip_policy(rate_limit.ip),
+            );
+        }
+        Ok(())
+    }
+
+    /// Credentials of an OAuth provider loaded through [`FarkConfig::oauth`].
+    pub fn oauth_provider(&self, name: &str) -> Option<OAuthProviderConfig> {
+        read(&self.oauth_providers).get(name).cloned()
+    }
+}
@@ -0,0 +1,42 @@
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// Request metadata that travels with an authentication attempt: it is handed to
+/// strategies, keys rate limiting and is attached to every [`AuthEvent`](crate::AuthEvent).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuthContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl AuthContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn client_ip(mut self, ip: IpAddr) -> Self {
+        self.client_ip = Some(ip);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    pub fn request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+}
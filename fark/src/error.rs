@@ -1,61 +1,273 @@
-use thiserror::*;
 #[cfg(feature = "actix")]
 use actix_web::{HttpResponse, ResponseError, http::StatusCode};
 use serde::Serialize;
+use std::sync::PoisonError;
+use thiserror::*;
 
+/// Boxed error kept as the source of [`AuthError::InternalError`].
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
+/// Errors returned by fark and by strategy closures.
+///
+/// `Display` is the internal detail, meant for logs. What a client may see is
+/// [`client_message`](AuthError::client_message) and [`code`](AuthError::code).
 #[derive(Debug, Error)]
 pub enum AuthError {
-    #[error("Invaid Input supplied!")]
+    #[error("invalid input supplied")]
     InvalidInput,
-    #[error("User Error!")]
+    #[error("user not found or not allowed")]
     UserError,
-    #[error("Strategy provided not found!")]
+    #[error("strategy not found")]
     StrategyNotFound,
-    #[error("Wrong password, check and try again!")]
+    #[error("wrong password")]
     PasswordMismatch,
-    #[error("Sorry, internal error!")]
-    InternalError,
-    #[error("Token generation error!")]
+    #[error("internal error{}", describe_source(.source))]
+    InternalError { source: Option<BoxError> },
+    #[error("token generation failed")]
     TokenError,
-    #[error("secret not supplied! ")]
+    #[error("jwt secret not configured")]
     SecretNotFound,
     #[error("invalid token provided")]
     InvalidToken,
     #[error("invalid pin")]
     PinMisMatch,
+    #[error("invalid one-time code")]
+    CodeMisMatch,
+    #[error("authentication flow not found")]
+    FlowNotFound,
+    #[error("strategy not allowed at this step")]
+    StepNotAllowed,
+    #[error("credential verification failed")]
+    CredentialRejected,
+    #[error("too many attempts, retry after {retry_after} seconds")]
+    TooManyAttempts { retry_after: u64 },
+    /// A strategy attempt ran past the timeout of its [`ExecutionPolicy`](crate::ExecutionPolicy).
+    #[error("strategy timed out")]
+    Timeout,
+    /// The strategy's circuit breaker is open after repeated failures.
+    #[error("strategy unavailable, retry after {retry_after} seconds")]
+    CircuitOpen { retry_after: u64 },
+    /// A credential error collapsed by [`ResponsePolicy::Uniform`]; `cause` is the
+    /// specific error, for logs only.
+    #[error("authentication failed: {cause}")]
+    AuthenticationFailed {
+        #[source]
+        cause: Box<AuthError>,
+    },
+    /// A valid OAuth access token that was not granted a scope the resource needs.
+    #[error("token lacks a required scope")]
+    InsufficientScope,
+}
+
+fn describe_source(source: &Option<BoxError>) -> String {
+    source
+        .as_ref()
+        .map(|source| format!(": {source}"))
+        .unwrap_or_default()
 }
 
-#[derive(Debug)]
+impl AuthError {
+    /// An [`AuthError::InternalError`] caused by `source`, e.g. a database or network error
+    /// raised inside a strategy.
+    pub fn internal(source: impl Into<BoxError>) -> Self {
+        AuthError::InternalError {
+            source: Some(source.into()),
+        }
+    }
+
+    /// Stable snake_case code of the variant, for clients, logs, metrics and audit
+    /// events. Codes are never renamed once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::InvalidInput => "invalid_input",
+            AuthError::UserError => "user_error",
+            AuthError::StrategyNotFound => "strategy_not_found",
+            AuthError::PasswordMismatch => "password_mismatch",
+            AuthError::InternalError { .. } => "internal_error",
+            AuthError::TokenError => "token_error",
+            AuthError::SecretNotFound => "secret_not_found",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::PinMisMatch => "pin_mismatch",
+            AuthError::CodeMisMatch => "code_mismatch",
+            AuthError::FlowNotFound => "flow_not_found",
+            AuthError::StepNotAllowed => "step_not_allowed",
+            AuthError::CredentialRejected => "credential_rejected",
+            AuthError::TooManyAttempts { .. } => "too_many_attempts",
+            AuthError::Timeout => "timeout",
+            AuthError::CircuitOpen { .. } => "circuit_open",
+            AuthError::AuthenticationFailed { .. } => "authentication_failed",
+            AuthError::InsufficientScope => "insufficient_scope",
+        }
+    }
+
+    /// Message that is safe to show to the client. Server-side failures all read the
+    /// same so configuration and infrastructure details stay in the logs.
+    pub fn client_message(&self) -> &'static str {
+        match self {
+            AuthError::InvalidInput => "Invalid input supplied.",
+            AuthError::UserError => "Unknown or disabled user.",
+            AuthError::PasswordMismatch => "Wrong password, check and try again.",
+            AuthError::InvalidToken | AuthError::TokenError => "Invalid or expired token.",
+            AuthError::PinMisMatch => "Invalid PIN.",
+            AuthError::CodeMisMatch => "Invalid one-time code.",
+            AuthError::StepNotAllowed => "This sign-in method is not allowed at this step.",
+            AuthError::CredentialRejected => "Credential verification failed.",
+            AuthError::TooManyAttempts { .. } => "Too many attempts, try again later.",
+            AuthError::Timeout | AuthError::CircuitOpen { .. } => {
+                "Sign-in is temporarily unavailable, try again later."
+            }
+            AuthError::AuthenticationFailed { .. } => "Invalid credentials.",
+            AuthError::InsufficientScope => "The token does not grant access to this resource.",
+            AuthError::StrategyNotFound
+            | AuthError::FlowNotFound
+            | AuthError::SecretNotFound
+            | AuthError::InternalError { .. } => "Sorry, an internal error occurred.",
+        }
+    }
+
+    /// Whether the error means the caller presented bad credentials, as opposed to a
+    /// misconfiguration or an internal failure.
+    pub fn is_credential_error(&self) -> bool {
+        matches!(
+            self,
+            AuthError::InvalidInput
+                | AuthError::UserError
+                | AuthError::PasswordMismatch
+                | AuthError::InvalidToken
+                | AuthError::PinMisMatch
+                | AuthError::CodeMisMatch
+                | AuthError::CredentialRejected
+                | AuthError::AuthenticationFailed { .. }
+        )
+    }
+
+    /// Whether retrying the same attempt may succeed: an internal failure such as a
+    /// dropped connection, or a timeout.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, AuthError::InternalError { .. } | AuthError::Timeout)
+    }
+
+    /// The specific error behind a collapsed [`AuthError::AuthenticationFailed`], or
+    /// `self` for any other error.
+    pub fn cause(&self) -> &AuthError {
+        match self {
+            AuthError::AuthenticationFailed { cause } => cause,
+            other => other,
+        }
+    }
+
+    /// HTTP status code the error maps to in framework integrations.
+    pub fn http_status(&self) -> u16 {
+        match self {
+            AuthError::InvalidInput => 401,
+            AuthError::StrategyNotFound
+            | AuthError::FlowNotFound
+            | AuthError::SecretNotFound
+            | AuthError::InternalError { .. } => 500,
+            AuthError::TokenError
+            | AuthError::InvalidToken
+            | AuthError::CredentialRejected
+            | AuthError::AuthenticationFailed { .. } => 401,
+            AuthError::InsufficientScope => 403,
+            AuthError::TooManyAttempts { .. } => 429,
+            AuthError::CircuitOpen { .. } => 503,
+            AuthError::Timeout => 504,
+            _ => 400,
+        }
+    }
+
+    /// RFC 7807 problem details for the error, without any internal detail.
+    pub fn problem_details(&self) -> ProblemDetails {
+        ProblemDetails {
+            problem_type: format!("urn:fark:error:{}", self.code()),
+            title: self.client_message(),
+            status: self.http_status(),
+            code: self.code(),
+            retry_after: match self {
+                AuthError::TooManyAttempts { retry_after }
+                | AuthError::CircuitOpen { retry_after } => Some(*retry_after),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// How [`Fark::authenticate`](crate::Fark::authenticate) reports credential errors to
+/// its caller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponsePolicy {
+    /// Return the specific error, e.g. [`AuthError::UserError`] vs
+    /// [`AuthError::PasswordMismatch`].
+    #[default]
+    Detailed,
+    /// Wrap every credential error in [`AuthError::AuthenticationFailed`], so clients
+    /// cannot tell an unknown user from a wrong password. Events and traces still
+    /// record the specific error.
+    Uniform,
+}
+
+impl ResponsePolicy {
+    pub(crate) fn apply(self, err: AuthError) -> AuthError {
+        match self {
+            ResponsePolicy::Uniform if err.is_credential_error() => {
+                AuthError::AuthenticationFailed {
+                    cause: Box::new(err),
+                }
+            }
+            _ => err,
+        }
+    }
+}
+
+impl From<TimeError> for AuthError {
+    fn from(err: TimeError) -> Self {
+        AuthError::internal(err)
+    }
+}
+
+impl<T> From<PoisonError<T>> for AuthError {
+    fn from(err: PoisonError<T>) -> Self {
+        AuthError::internal(err.to_string())
+    }
+}
+
+/// `application/problem+json` body (RFC 7807) with fark's `code` extension member.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: &'static str,
+    pub status: u16,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+#[derive(Debug, Error)]
 pub enum TimeError {
+    #[error("system clock is before the unix epoch")]
     TimeGenError,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum CoreError {
+    #[error("failed to parse input")]
     ParseError,
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-    message: String,
-}
-
 #[cfg(feature = "actix")]
 impl ResponseError for AuthError {
     fn status_code(&self) -> StatusCode {
-        match self {
-            AuthError::InvalidInput => StatusCode::UNAUTHORIZED,
-            AuthError::StrategyNotFound => StatusCode::INTERNAL_SERVER_ERROR,
-            AuthError::SecretNotFound => StatusCode::INTERNAL_SERVER_ERROR,
-            AuthError::TokenError | AuthError::InvalidToken => StatusCode::UNAUTHORIZED,
-            _ => StatusCode::BAD_REQUEST,
-        }
+        StatusCode::from_u16(self.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(ErrorResponse {
-            message: self.to_string(),
-        })
+        let problem = self.problem_details();
+        let mut response = HttpResponse::build(self.status_code());
+        response.content_type("application/problem+json");
+        if let Some(retry_after) = problem.retry_after {
+            response.insert_header(("Retry-After", retry_after.to_string()));
+        }
+        response.json(problem)
     }
 }
@@ -0,0 +1,134 @@
+use crate::context::AuthContext;
+use crate::error::AuthError;
+use crate::fark::{read, write};
+use crate::time::now;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// What happened in an [`AuthEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthEventKind {
+    /// A strategy ran through [`Fark::authenticate`](crate::Fark::authenticate).
+    Login,
+    /// One member of a strategy chain was tried; the chain itself reports a `Login`.
+    Attempt,
+    /// [`Fark::issue_jwt`](crate::Fark::issue_jwt) was called.
+    TokenIssued,
+    /// [`Fark::verify_jwt`](crate::Fark::verify_jwt) rejected a token.
+    TokenRejected,
+    /// [`Fark::logout`](crate::Fark::logout) was called.
+    Logout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthOutcome {
+    Success,
+    Failure,
+}
+
+/// A security-relevant event, delivered to every handler registered with
+/// [`Fark::on_event`](crate::Fark::on_event). Never carries secrets or tokens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuthEvent {
+    pub kind: AuthEventKind,
+    pub outcome: AuthOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<String>,
+    /// The chain an `Attempt` ran in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+    /// The authenticated user, or on failure the account the caller claimed, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// [`AuthError::code`] of the failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub context: AuthContext,
+}
+
+impl AuthEvent {
+    pub(crate) fn new(kind: AuthEventKind, result: Result<(), &AuthError>) -> Self {
+        Self {
+            kind,
+            outcome: if result.is_ok() {
+                AuthOutcome::Success
+            } else {
+                AuthOutcome::Failure
+            },
+            strategy: None,
+            chain: None,
+            subject: None,
+            error: result.err().map(AuthError::code),
+            timestamp: now().unwrap_or_default(),
+            context: AuthContext::default(),
+        }
+    }
+}
+
+/// Receives [`AuthEvent`]s. Implemented for any `Fn(&AuthEvent)` closure.
+///
+/// Handlers run inline on the authenticating task, so hand slow work (network
+/// shipping, database writes) off to a channel.
+pub trait AuthEventHandler: Send + Sync {
+    fn handle(&self, event: &AuthEvent);
+}
+
+impl<F> AuthEventHandler for F
+where
+    F: Fn(&AuthEvent) + Send + Sync,
+{
+    fn handle(&self, event: &AuthEvent) {
+        self(event)
+    }
+}
+
+/// Audit sink writing one JSON object per line to any writer (a file, stdout, a pipe).
+pub struct JsonLinesAuditSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLinesAuditSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write + Send> AuthEventHandler for JsonLinesAuditSink<W> {
+    fn handle(&self, event: &AuthEvent) {
+        let Ok(mut line) = serde_json::to_vec(event) else {
+            return;
+        };
+        line.push(b'\n');
+        if let Ok(mut writer) = self.writer.lock() {
+            // Audit logging must never fail a login, so write errors are dropped.
+            let _ = writer.write_all(&line).and_then(|_| writer.flush());
+        }
+    }
+}
+
+impl super::fark::Fark {
+    /// Registers a handler for authentication events. Handlers are called in
+    /// registration order.
+    pub fn on_event<H>(self, handler: H) -> Self
+    where
+        H: AuthEventHandler + 'static,
+    {
+        write(&self.event_handlers).push(Arc::new(handler));
+        self
+    }
+
+    pub(crate) fn emit(&self, event: AuthEvent) {
+        // Copied out so a handler can use this `Fark` without deadlocking.
+        let handlers = read(&self.event_handlers).clone();
+        for handler in &handlers {
+            handler.handle(&event);
+        }
+    }
+}
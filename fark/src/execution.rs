@@ -0,0 +1,234 @@
+use crate::context::AuthContext;
+use crate::error::AuthError;
+use crate::fark::Fark;
+use crate::identity::Identity;
+use crate::input::AuthInput;
+use crate::strategy::Strategy;
+use std::collections::HashMap;
+use std::sync::{MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Limits on how a strategy runs: a timeout per attempt, retries for transient
+/// failures and a circuit breaker for backends that keep failing.
+///
+/// Set one per strategy with [`Fark::with_execution_policy`] or for every strategy
+/// with [`Fark::with_default_execution_policy`]. The default policy imposes nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionPolicy {
+    /// Budget for a single attempt. An attempt that runs over is dropped, cancelling
+    /// its future, and fails with [`AuthError::Timeout`].
+    pub timeout: Option<Duration>,
+    pub retry: Option<RetryPolicy>,
+    pub circuit_breaker: Option<CircuitBreakerPolicy>,
+}
+
+impl ExecutionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn circuit_breaker(mut self, circuit_breaker: CircuitBreakerPolicy) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+}
+
+/// Retries attempts that fail with a [transient](AuthError::is_transient) error,
+/// waiting `base_delay * 2^n` (capped at `max_delay`) before retry `n`. Credential
+/// errors are never retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Up to `max_retries` retries after 100ms, 200ms, 400ms... capped at 2 seconds.
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(1u32 << retry.min(31))
+            .min(self.max_delay)
+    }
+}
+
+/// Opens after `failure_threshold` consecutive transient failures. While open the
+/// strategy is not called and fails fast with [`AuthError::CircuitOpen`]; after
+/// `reset_after` one trial call is let through, which closes the circuit on success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerPolicy {
+    pub failure_threshold: u32,
+    pub reset_after: Duration,
+}
+
+impl CircuitBreakerPolicy {
+    pub fn new(failure_threshold: u32, reset_after: Duration) -> Self {
+        Self {
+            failure_threshold,
+            reset_after,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Execution policies and circuit breaker state, shared by every clone of a `Fark`.
+#[derive(Debug, Default)]
+pub(crate) struct Execution {
+    default: ExecutionPolicy,
+    policies: HashMap<String, ExecutionPolicy>,
+    breakers: HashMap<String, BreakerState>,
+}
+
+impl Execution {
+    fn policy(&self, strategy: &str) -> ExecutionPolicy {
+        self.policies.get(strategy).copied().unwrap_or(self.default)
+    }
+
+    /// Time left until the circuit of `strategy` may be tried again.
+    fn open_for(&self, strategy: &str, now: Instant) -> Option<Duration> {
+        self.breakers
+            .get(strategy)
+            .and_then(|state| state.open_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// Fails while the circuit of `strategy` is open. Once `reset_after` has passed
+    /// the caller becomes the trial call and the circuit stays open for everyone else.
+    fn acquire(
+        &mut self,
+        strategy: &str,
+        policy: CircuitBreakerPolicy,
+        now: Instant,
+    ) -> Result<(), AuthError> {
+        if let Some(remaining) = self.open_for(strategy, now) {
+            return Err(AuthError::CircuitOpen {
+                retry_after: remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0),
+            });
+        }
+        if let Some(state) = self.breakers.get_mut(strategy)
+            && state.open_until.is_some()
+        {
+            state.open_until = Some(now + policy.reset_after);
+        }
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        strategy: &str,
+        policy: CircuitBreakerPolicy,
+        result: &Result<Identity, AuthError>,
+        now: Instant,
+    ) {
+        match result {
+            Err(err) if err.is_transient() => {
+                let state = self.breakers.entry(strategy.to_string()).or_default();
+                state.failures += 1;
+                if state.failures >= policy.failure_threshold {
+                    state.open_until = Some(now + policy.reset_after);
+                }
+            }
+            _ => {
+                self.breakers.remove(strategy);
+            }
+        }
+    }
+}
+
+impl Fark {
+    /// Applies `policy` to the strategy `name`, overriding the default policy.
+    pub fn with_execution_policy(self, name: impl Into<String>, policy: ExecutionPolicy) -> Self {
+        self.set_execution_policy(name, policy);
+        self
+    }
+
+    /// Applies `policy` to every strategy without a policy of its own.
+    pub fn with_default_execution_policy(self, policy: ExecutionPolicy) -> Self {
+        self.lock_execution().default = policy;
+        self
+    }
+
+    /// Replaces the policy of the strategy `name` at runtime. Attempts already running
+    /// keep the policy they started with.
+    pub fn set_execution_policy(&self, name: impl Into<String>, policy: ExecutionPolicy) {
+        self.lock_execution().policies.insert(name.into(), policy);
+    }
+
+    /// Runs one strategy under its execution policy.
+    pub(crate) async fn execute(
+        &self,
+        name: &str,
+        strategy: &Strategy,
+        input: AuthInput,
+        ctx: AuthContext,
+    ) -> Result<Identity, AuthError> {
+        let policy = {
+            let mut execution = self.lock_execution();
+            let policy = execution.policy(name);
+            if let Some(breaker) = policy.circuit_breaker {
+                execution.acquire(name, breaker, Instant::now())?;
+            }
+            policy
+        };
+
+        let mut retries = 0;
+        loop {
+            let attempt = strategy(input.clone(), ctx.clone());
+            let result = match policy.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, attempt)
+                    .await
+                    .unwrap_or(Err(AuthError::Timeout)),
+                None => attempt.await,
+            };
+
+            let mut circuit_open = false;
+            if let Some(breaker) = policy.circuit_breaker {
+                let mut execution = self.lock_execution();
+                let now = Instant::now();
+                execution.record(name, breaker, &result, now);
+                circuit_open = execution.open_for(name, now).is_some();
+            }
+
+            match (&result, policy.retry) {
+                (Err(err), Some(retry))
+                    if err.is_transient() && retries < retry.max_retries && !circuit_open =>
+                {
+                    tokio::time::sleep(retry.delay(retries)).await;
+                    retries += 1;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    fn lock_execution(&self) -> MutexGuard<'_, Execution> {
+        self.execution
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
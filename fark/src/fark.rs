@@ -1,31 +1,98 @@
-use crate::AuthError; 
+use crate::api_key::{ApiKeyRecord, ApiKeys};
+use crate::config::{ConfigError, OAuthProviderConfig};
+use crate::context::AuthContext;
+use crate::error::{AuthError, ResponsePolicy};
+use crate::events::{AuthEvent, AuthEventHandler, AuthEventKind};
+use crate::execution::Execution;
+use crate::flow::AuthFlow;
+use crate::hotp::{Hotp, HotpStore};
+use crate::http_auth::DigestAuth;
 use crate::identity::Identity;
 use crate::input::AuthInput;
+use crate::introspection::{InMemoryRevocationStore, RevocationStore};
+use crate::jwt::JwtSettings;
+use crate::magic_link::MagicLink;
+use crate::otp::OneTimeCode;
+use crate::recovery::{RecoveryCodeStore, hash_recovery_code};
+use crate::remote_introspection::RemoteIntrospection;
+#[cfg(feature = "saml")]
+use crate::saml::SamlServiceProvider;
+use crate::secret::Secret;
 use crate::strategy::Strategy;
-use std::collections::HashMap;
+use crate::telemetry;
+use crate::throttle::Throttle;
+use crate::totp::{Totp, TotpSecret};
+use crate::webauthn::WebAuthn;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
 
+/// The authenticator. Cloning is cheap and clones share strategies, flows, signing
+/// keys, rate limits and configuration, so a `Fark` can be handed to every worker and
+/// still be changed at runtime with [`register_strategy`](Fark::register_strategy),
+/// [`set_jwt_secret`](Fark::set_jwt_secret) or [`reload`](Fark::reload). Event
+/// handlers and the [`ResponsePolicy`] are shared the same way.
+#[derive(Clone)]
 pub struct Fark {
-    pub(crate) strategies: HashMap<String, Strategy>,
-    pub(crate) secret: String,
+    pub(crate) strategies: Arc<RwLock<HashMap<String, Arc<Strategy>>>>,
+    pub(crate) chains: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    pub(crate) jwt: Arc<RwLock<JwtSettings>>,
+    pub(crate) flows: Arc<RwLock<HashMap<String, AuthFlow>>>,
+    pub(crate) throttle: Arc<RwLock<Option<Throttle>>>,
+    pub(crate) event_handlers: Arc<RwLock<Vec<Arc<dyn AuthEventHandler>>>>,
+    pub(crate) response_policy: Arc<RwLock<ResponsePolicy>>,
+    pub(crate) enabled_strategies: Arc<RwLock<Option<HashSet<String>>>>,
+    pub(crate) oauth_providers: Arc<RwLock<HashMap<String, OAuthProviderConfig>>>,
+    pub(crate) execution: Arc<Mutex<Execution>>,
+    pub(crate) revocations: Arc<RwLock<Option<Arc<dyn RevocationStore>>>>,
+    /// Used flow tokens, when no revocation store is configured.
+    pub(crate) used_pending_tokens: Arc<InMemoryRevocationStore>,
+}
+
+impl Default for Fark {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Fark {
     pub fn new() -> Self {
         Self {
-            strategies: HashMap::new(),
-            secret: String::new(),
+            strategies: Arc::default(),
+            chains: Arc::default(),
+            jwt: Arc::default(),
+            flows: Arc::default(),
+            throttle: Arc::default(),
+            event_handlers: Arc::default(),
+            response_policy: Arc::default(),
+            enabled_strategies: Arc::default(),
+            oauth_providers: Arc::default(),
+            execution: Arc::default(),
+            revocations: Arc::default(),
+            used_pending_tokens: Arc::default(),
         }
     }
 
-    pub fn with_local<F, Fut>(mut self, f: F) -> Self
+    pub fn with_local<F, Fut>(self, f: F) -> Self
     where
         F: Fn(HashMap<String, String>) -> Fut + Send + Sync + 'static,
         Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
     {
-        self.strategies.insert(
+        self.with_local_context(move |data, _| f(data))
+    }
+
+    /// Like [`with_local`](Self::with_local), for closures that also need the request
+    /// [`AuthContext`].
+    pub fn with_local_context<F, Fut>(self, f: F) -> Self
+    where
+        F: Fn(HashMap<String, String>, AuthContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        self.insert_strategy(
             "local".into(),
-            Box::new(move |input: AuthInput| match input {
-                AuthInput::Local { data } => Box::pin(f(data)),
+            Box::new(move |input: AuthInput, ctx: AuthContext| match input {
+                AuthInput::Local { data } => Box::pin(f(data, ctx)),
                 _ => Box::pin(async { Err(AuthError::InvalidInput) }),
             }),
         );
@@ -33,14 +100,14 @@ impl Fark {
         self
     }
 
-    pub fn with_google<F, Fut>(mut self, f: F) -> Self
+    pub fn with_google<F, Fut>(self, f: F) -> Self
     where
         F: Fn(String, String, String, Vec<String>) -> Fut + Send + Sync + 'static,
         Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
     {
-        self.strategies.insert(
+        self.insert_strategy(
             "google".into(),
-            Box::new(move |input: AuthInput| match input {
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
                 AuthInput::Google {
                     client_id,
                     client_secret,
@@ -53,30 +120,670 @@ impl Fark {
         self
     }
 
-    pub fn with_pin<F, Fut>(mut self, f: F) -> Self
+    pub fn with_pin<F, Fut>(self, f: F) -> Self
     where
         F: Fn(i32) -> Fut + Send + Sync + 'static,
         Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
     {
-        self.strategies.insert(
+        self.with_pin_context(move |pin_code, _| f(pin_code))
+    }
+
+    /// Like [`with_pin`](Self::with_pin), for closures that also need the request
+    /// [`AuthContext`].
+    pub fn with_pin_context<F, Fut>(self, f: F) -> Self
+    where
+        F: Fn(i32, AuthContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        self.insert_strategy(
             "pin".into(),
-            Box::new(move |input: AuthInput| match input {
-                AuthInput::Pin { pin_code } => Box::pin(f(pin_code)),
+            Box::new(move |input: AuthInput, ctx: AuthContext| match input {
+                AuthInput::Pin { pin_code } => Box::pin(f(pin_code, ctx)),
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers a custom strategy under `name`. `f` receives every input passed to
+    /// `authenticate(name, ..)` together with the request context.
+    pub fn with_strategy<F, Fut>(self, name: impl Into<String>, f: F) -> Self
+    where
+        F: Fn(AuthInput, AuthContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        self.insert_strategy(
+            name.into(),
+            Box::new(move |input: AuthInput, ctx: AuthContext| Box::pin(f(input, ctx))),
+        );
+        self
+    }
+
+    /// Registers a chain under `name`: `authenticate(name, ..)` tries each strategy in
+    /// `members` in order with the same input and returns the first success, e.g. a
+    /// directory login falling back to the local database.
+    ///
+    /// Members are looked up among plain strategies only, so a chain may share its
+    /// name with one of them: `with_chain("local", ["ldap", "local"])`.
+    pub fn with_chain<I, S>(self, name: impl Into<String>, members: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.register_chain(name, members);
+        self
+    }
+
+    /// Registers the `"hotp"` strategy. `f` looks up the account and its token secret;
+    /// the moving counter lives in `store`.
+    pub fn with_hotp<S, F, Fut>(self, hotp: Hotp, store: S, f: F) -> Self
+    where
+        S: HotpStore + 'static,
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(Identity, TotpSecret), AuthError>> + Send + 'static,
+    {
+        let store = Arc::new(store);
+        self.insert_strategy(
+            "hotp".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::Hotp { account, code } => {
+                    let hotp = hotp.clone();
+                    let store = store.clone();
+                    let lookup = f(account);
+                    Box::pin(async move {
+                        let (identity, secret) = lookup.await?;
+                        let counter = store.counter(&identity.user_id).await?;
+                        let next = hotp
+                            .verify(&secret, &code, counter)
+                            .ok_or(AuthError::CodeMisMatch)?;
+                        if !store.advance(&identity.user_id, counter, next).await? {
+                            return Err(AuthError::CodeMisMatch);
+                        }
+                        Ok(identity)
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the `"recovery_code"` strategy. Each code is consumed from `store`
+    /// on first use.
+    pub fn with_recovery_codes<S, F, Fut>(self, store: S, f: F) -> Self
+    where
+        S: RecoveryCodeStore + 'static,
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        let store = Arc::new(store);
+        self.insert_strategy(
+            "recovery_code".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::RecoveryCode { account, code } => {
+                    let store = store.clone();
+                    let lookup = f(account);
+                    Box::pin(async move {
+                        let identity = lookup.await?;
+                        let hash = hash_recovery_code(&code);
+                        if !store.consume(&identity.user_id, &hash).await? {
+                            return Err(AuthError::CodeMisMatch);
+                        }
+                        Ok(identity)
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the `"totp"` strategy. `f` looks up the account named in the input
+    /// and returns its identity together with the enrolled secret.
+    pub fn with_totp<F, Fut>(self, totp: Totp, f: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(Identity, TotpSecret), AuthError>> + Send + 'static,
+    {
+        self.insert_strategy(
+            "totp".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::Totp { account, code } => {
+                    let totp = totp.clone();
+                    let lookup = f(account);
+                    Box::pin(async move {
+                        let (identity, secret) = lookup.await?;
+                        totp.verify(&identity.user_id, &secret, &code).await?;
+                        Ok(identity)
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the `"otp"` strategy for codes issued with [`OneTimeCode::issue`].
+    /// `f` loads the identity once the code checks out.
+    pub fn with_otp<F, Fut>(self, otp: OneTimeCode, f: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.insert_strategy(
+            "otp".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::Otp { account, code } => {
+                    let otp = otp.clone();
+                    let f = f.clone();
+                    Box::pin(async move {
+                        otp.verify(&account, &code).await?;
+                        f(account).await
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the `"magic_link"` strategy. `f` loads the identity for the email
+    /// address the verified link was sent to.
+    pub fn with_magic_link<F, Fut>(self, magic_link: MagicLink, f: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.insert_strategy(
+            "magic_link".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::MagicLink { token } => {
+                    let magic_link = magic_link.clone();
+                    let f = f.clone();
+                    Box::pin(async move {
+                        let email = magic_link.verify(&token).await?;
+                        f(email).await
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the `"webauthn"` strategy. `f` loads the identity of the user the
+    /// verified credential belongs to.
+    pub fn with_webauthn<F, Fut>(self, webauthn: WebAuthn, f: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.insert_strategy(
+            "webauthn".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::WebAuthn {
+                    credential_id,
+                    client_data_json,
+                    authenticator_data,
+                    signature,
+                } => {
+                    let webauthn = webauthn.clone();
+                    let f = f.clone();
+                    Box::pin(async move {
+                        let user_id = webauthn
+                            .finish_authentication(
+                                &credential_id,
+                                &client_data_json,
+                                &authenticator_data,
+                                &signature,
+                            )
+                            .await?;
+                        f(user_id).await
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the `"api_key"` strategy. `f` builds the identity from the verified
+    /// key's record. Unless `f` sets a `scope` field of its own, the key's scopes are
+    /// added to the identity data as a space-separated `scope`, so
+    /// [`Identity::has_scope`] works for API keys as for OAuth access tokens.
+    pub fn with_api_keys<F, Fut>(self, api_keys: ApiKeys, f: F) -> Self
+    where
+        F: Fn(ApiKeyRecord) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.insert_strategy(
+            "api_key".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::ApiKey { key } => {
+                    let api_keys = api_keys.clone();
+                    let f = f.clone();
+                    Box::pin(async move {
+                        let record = api_keys.verify(&key).await?;
+                        let scope = record.scopes.join(" ");
+                        let mut identity = f(record).await?;
+                        add_key_scope(&mut identity, scope);
+                        Ok(identity)
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the `"digest"` strategy for HTTP Digest credentials. `f` looks up the
+    /// user and returns the identity together with the plaintext password.
+    pub fn with_digest<F, Fut>(self, digest: DigestAuth, f: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(Identity, Secret), AuthError>> + Send + 'static,
+    {
+        self.insert_strategy(
+            "digest".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::Digest {
+                    credentials,
+                    method,
+                } => {
+                    let digest = digest.clone();
+                    let lookup = f(credentials.username.clone());
+                    Box::pin(async move {
+                        let (identity, password) = lookup.await?;
+                        digest.verify(&credentials, &method, password.expose())?;
+                        Ok(identity)
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the `"introspection"` strategy for opaque bearer tokens of another
+    /// issuer. `f` gets the identity mapped from the introspection response and may
+    /// check its scopes or replace it with a local account.
+    pub fn with_introspection<F, Fut>(self, remote: RemoteIntrospection, f: F) -> Self
+    where
+        F: Fn(Identity) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.insert_strategy(
+            "introspection".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::BearerToken { token } => {
+                    let remote = remote.clone();
+                    let f = f.clone();
+                    Box::pin(async move {
+                        let identity = remote.verify(&token).await?;
+                        f(identity).await
+                    })
+                }
+                _ => Box::pin(async { Err(AuthError::InvalidInput) }),
+            }),
+        );
+        self
+    }
+
+    /// Registers the "saml" strategy, accepting responses of the IdP `sp` trusts.
+    /// `f` gets the identity mapped from the assertion and may look up or provision
+    /// the local account.
+    #[cfg(feature = "saml")]
+    pub fn with_saml<F, Fut>(self, sp: SamlServiceProvider, f: F) -> Self
+    where
+        F: Fn(Identity) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        self.insert_strategy(
+            "saml".into(),
+            Box::new(move |input: AuthInput, _: AuthContext| match input {
+                AuthInput::Saml {
+                    response,
+                    request_id,
+                } => {
+                    let sp = sp.clone();
+                    let f = f.clone();
+                    Box::pin(async move {
+                        let identity = sp.validate(&response, request_id.as_deref()).await?;
+                        f(identity).await
+                    })
+                }
                 _ => Box::pin(async { Err(AuthError::InvalidInput) }),
             }),
         );
         self
     }
-    pub fn with_jwt(&mut self, secret: String) {
-        self.secret = secret;
+
+    /// Rate-limits every strategy by account identifier and client IP.
+    pub fn with_throttle(self, throttle: Throttle) -> Self {
+        *write(&self.throttle) = Some(throttle);
+        self
+    }
+
+    /// Sets how credential errors from `authenticate` are reported. See [`ResponsePolicy`].
+    pub fn with_response_policy(self, policy: ResponsePolicy) -> Self {
+        *write(&self.response_policy) = policy;
+        self
+    }
+
+    /// Sets the HMAC key used to sign and verify tokens. See
+    /// [`set_jwt_secret`](Self::set_jwt_secret).
+    pub fn with_jwt(self, secret: impl Into<Secret>) -> Result<Self, ConfigError> {
+        self.set_jwt_secret(secret)?;
+        Ok(self)
+    }
+
+    /// Registers or replaces the strategy `name` on this `Fark` and every clone of it.
+    /// Takes effect for the next `authenticate` call; attempts already running finish
+    /// with the strategy they started with.
+    pub fn register_strategy<F, Fut>(&self, name: impl Into<String>, f: F)
+    where
+        F: Fn(AuthInput, AuthContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Identity, AuthError>> + Send + 'static,
+    {
+        self.insert_strategy(
+            name.into(),
+            Box::new(move |input: AuthInput, ctx: AuthContext| Box::pin(f(input, ctx))),
+        );
+    }
+
+    /// Registers or replaces a chain at runtime. See [`with_chain`](Self::with_chain).
+    pub fn register_chain<I, S>(&self, name: impl Into<String>, members: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let members = members.into_iter().map(Into::into).collect();
+        write(&self.chains).insert(name.into(), members);
+    }
+
+    /// Unregisters the strategy or chain `name`, returning whether it was registered.
+    pub fn remove_strategy(&self, name: &str) -> bool {
+        let chain = write(&self.chains).remove(name).is_some();
+        write(&self.strategies).remove(name).is_some() || chain
+    }
+
+    pub fn has_strategy(&self, name: &str) -> bool {
+        read(&self.chains).contains_key(name) || read(&self.strategies).contains_key(name)
+    }
+
+    /// Names of the registered strategies and chains, sorted.
+    pub fn strategy_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = read(&self.strategies).keys().cloned().collect();
+        names.extend(read(&self.chains).keys().cloned());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Restricts `authenticate` to the given strategies, e.g. from a feature flag.
+    /// `None` allows every registered strategy.
+    pub fn set_enabled_strategies<I, S>(&self, names: Option<I>)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        *write(&self.enabled_strategies) =
+            names.map(|names| names.into_iter().map(Into::into).collect());
+    }
+
+    /// Replaces the HMAC key used to sign and verify tokens. Tokens signed with the
+    /// previous key stop verifying. The key must pass the same strength check as
+    /// `jwt.secret` in the configuration file.
+    pub fn set_jwt_secret(&self, secret: impl Into<Secret>) -> Result<(), ConfigError> {
+        let secret = secret.into();
+        let mut jwt = write(&self.jwt);
+        secret
+            .check_hmac_strength(jwt.algorithm)
+            .map_err(|message| ConfigError::field("jwt.secret", message))?;
+        jwt.secret = secret;
+        jwt.build_keys()
+    }
+
+    fn insert_strategy(&self, name: String, strategy: Strategy) {
+        write(&self.strategies).insert(name, Arc::new(strategy));
     }
 
     pub async fn authenticate(&self, name: &str, input: AuthInput) -> Result<Identity, AuthError> {
-        let strategy = self
-            .strategies
+        self.authenticate_with_context(name, input, AuthContext::default())
+            .await
+    }
+
+    /// Authenticates with the strategy (or chain) registered under the input's
+    /// [`strategy_name`](AuthInput::strategy_name), e.g. `"totp"` for `AuthInput::Totp`.
+    pub async fn authenticate_auto(&self, input: AuthInput) -> Result<Identity, AuthError> {
+        self.authenticate_with_context(input.strategy_name(), input, AuthContext::default())
+            .await
+    }
+
+    /// Like [`authenticate`](Self::authenticate), passing request metadata to the
+    /// strategy, the [`Throttle`] and emitted events.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "fark.authenticate",
+            skip_all,
+            fields(
+                strategy = name,
+                subject = tracing::field::Empty,
+                error = tracing::field::Empty
+            )
+        )
+    )]
+    pub async fn authenticate_with_context(
+        &self,
+        name: &str,
+        input: AuthInput,
+        ctx: AuthContext,
+    ) -> Result<Identity, AuthError> {
+        let started = Instant::now();
+        let account = input.account().map(str::to_string);
+        let result = self
+            .run_strategy(name, input, account.as_deref(), ctx.clone())
+            .await;
+
+        let subject = match &result {
+            Ok(identity) => Some(identity.user_id.clone()),
+            Err(_) => account,
+        };
+        telemetry::record_outcome(subject.as_deref(), &result);
+        // Unregistered names are caller input and would make unbounded metric labels.
+        let label = match &result {
+            Err(AuthError::StrategyNotFound) if self.resolve(name).is_err() => "unknown",
+            _ => name,
+        };
+        telemetry::record_authenticate(label, started, &result);
+
+        let mut event = AuthEvent::new(AuthEventKind::Login, result.as_ref().map(|_| ()));
+        event.strategy = Some(name.to_string());
+        event.subject = subject;
+        event.context = ctx;
+        self.emit(event);
+
+        let policy = *read(&self.response_policy);
+        result.map_err(|err| policy.apply(err))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "fark.strategy", skip_all, fields(strategy = name))
+    )]
+    async fn run_strategy(
+        &self,
+        name: &str,
+        input: AuthInput,
+        account: Option<&str>,
+        ctx: AuthContext,
+    ) -> Result<Identity, AuthError> {
+        let target = self.resolve(name)?;
+        let throttle = read(&self.throttle).clone();
+
+        let reserved = match &throttle {
+            Some(throttle) => Some(throttle.attempt(name, account, ctx.client_ip).await?),
+            None => None,
+        };
+
+        let result = match target {
+            Target::Strategy(strategy) => self
+                .execute(name, &strategy, input, ctx)
+                .await
+                .map(|identity| with_amr(name, identity)),
+            Target::Chain(members) => self.run_chain(name, members, input, &ctx).await,
+        };
+
+        if let (Some(throttle), Some(reserved)) = (&throttle, reserved) {
+            match &result {
+                Ok(_) => throttle.record_success(reserved).await?,
+                Err(err) if err.is_credential_error() => {}
+                Err(_) => throttle.release(reserved).await?,
+            }
+        }
+        result
+    }
+
+    /// Tries each member in order, emitting an [`AuthEventKind::Attempt`] per member.
+    /// On failure the most relevant member error is returned, see [`relevance`].
+    async fn run_chain(
+        &self,
+        chain: &str,
+        members: Vec<String>,
+        input: AuthInput,
+        ctx: &AuthContext,
+    ) -> Result<Identity, AuthError> {
+        let account = input.account().map(str::to_string);
+        let mut failure: Option<AuthError> = None;
+        for member in members {
+            let result = match self.strategy(&member) {
+                Ok(strategy) => {
+                    self.execute(&member, &strategy, input.clone(), ctx.clone())
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+
+            let mut event = AuthEvent::new(AuthEventKind::Attempt, result.as_ref().map(|_| ()));
+            event.strategy = Some(member.clone());
+            event.chain = Some(chain.to_string());
+            event.subject = match &result {
+                Ok(identity) => Some(identity.user_id.clone()),
+                Err(_) => account.clone(),
+            };
+            event.context = ctx.clone();
+            self.emit(event);
+
+            match result {
+                Ok(identity) => return Ok(with_amr(&member, identity)),
+                Err(err) => {
+                    if failure
+                        .as_ref()
+                        .is_none_or(|best| relevance(&err) > relevance(best))
+                    {
+                        failure = Some(err);
+                    }
+                }
+            }
+        }
+        Err(failure.unwrap_or(AuthError::StrategyNotFound))
+    }
+
+    fn is_enabled(&self, name: &str) -> bool {
+        read(&self.enabled_strategies)
+            .as_ref()
+            .is_none_or(|enabled| enabled.contains(name))
+    }
+
+    fn resolve(&self, name: &str) -> Result<Target, AuthError> {
+        if !self.is_enabled(name) {
+            return Err(AuthError::StrategyNotFound);
+        }
+        if let Some(members) = read(&self.chains).get(name) {
+            return Ok(Target::Chain(members.clone()));
+        }
+        self.strategy(name).map(Target::Strategy)
+    }
+
+    fn strategy(&self, name: &str) -> Result<Arc<Strategy>, AuthError> {
+        if !self.is_enabled(name) {
+            return Err(AuthError::StrategyNotFound);
+        }
+        read(&self.strategies)
             .get(name)
-            .ok_or(AuthError::StrategyNotFound)?;
+            .cloned()
+            .ok_or(AuthError::StrategyNotFound)
+    }
+}
+
+/// What a name passed to `authenticate` refers to.
+enum Target {
+    Strategy(Arc<Strategy>),
+    Chain(Vec<String>),
+}
+
+/// Fills `amr` from the strategy name unless the strategy set it itself.
+fn with_amr(strategy: &str, mut identity: Identity) -> Identity {
+    if identity.amr.is_empty()
+        && let Some(method) = method_reference(strategy)
+    {
+        identity.amr.push(method.to_string());
+    }
+    identity
+}
 
-        strategy(input).await
+/// Adds an API key's scopes as the identity's `scope` unless it already has one.
+fn add_key_scope(identity: &mut Identity, scope: String) {
+    if scope.is_empty() {
+        return;
+    }
+    if identity.data.is_null() {
+        identity.data = Value::Object(Map::new());
     }
+    if let Value::Object(data) = &mut identity.data {
+        data.entry("scope").or_insert(Value::String(scope));
+    }
+}
+
+/// Ranks the errors of a failed chain so the most telling one reaches the caller: a
+/// wrong password beats an unknown user in a directory that never had the account,
+/// an outage beats that too, and a member that did not accept the input ranks last.
+fn relevance(err: &AuthError) -> u8 {
+    match err {
+        AuthError::StrategyNotFound | AuthError::InvalidInput => 0,
+        AuthError::UserError => 1,
+        err if err.is_credential_error() => 3,
+        _ => 2,
+    }
+}
+
+/// Maps a built-in strategy to its registered RFC 8176 authentication method
+/// reference. Federated logins, API keys and custom strategies have none; their
+/// strategy can set `amr` itself.
+fn method_reference(strategy: &str) -> Option<&'static str> {
+    match strategy {
+        "local" | "digest" => Some("pwd"),
+        "pin" => Some("pin"),
+        "totp" | "hotp" | "otp" | "recovery_code" => Some("otp"),
+        "magic_link" => Some("user"),
+        "webauthn" => Some("hwk"),
+        _ => None,
+    }
+}
+
+/// Locks shared state for reading. Every write is a single insert or replacement, so a
+/// panic elsewhere cannot leave the data half-updated and poisoning is ignored.
+pub(crate) fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
 }
@@ -0,0 +1,221 @@
+use crate::context::AuthContext;
+use crate::crypto::random_bytes;
+use crate::error::AuthError;
+use crate::fark::{read, write};
+use crate::identity::Identity;
+use crate::input::AuthInput;
+use crate::introspection::RevocationStore;
+use crate::time::now;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+const MFA_PENDING: &str = "mfa_pending";
+
+/// An ordered chain of authentication steps, e.g. a password followed by a second
+/// factor. Each step lists the strategies that may satisfy it.
+#[derive(Debug, Clone)]
+pub struct AuthFlow {
+    steps: Vec<Vec<String>>,
+    pending_ttl: u64,
+}
+
+impl Default for AuthFlow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuthFlow {
+    pub fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            pending_ttl: 300,
+        }
+    }
+
+    pub fn step<I, S>(mut self, strategies: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.steps
+            .push(strategies.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Lifetime of the partial token handed out between steps. Defaults to 5 minutes.
+    pub fn pending_ttl(mut self, secs: u64) -> Self {
+        self.pending_ttl = secs;
+        self
+    }
+}
+
+/// Result of running one step of a flow.
+#[derive(Debug)]
+pub enum FlowStep {
+    /// More steps are required. `token` is a short-lived "MFA pending" token to pass
+    /// to [`Fark::continue_flow`](crate::Fark::continue_flow); it is rejected by `verify_jwt`
+    /// and stops working once its step succeeds.
+    Pending { token: String, next: Vec<String> },
+    /// Every step succeeded; `amr` on the identity lists the methods used.
+    Complete(Identity),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingClaims {
+    exp: u64,
+    iat: u64,
+    sub: String,
+    extra: Value,
+    amr: Vec<String>,
+    typ: String,
+    jti: String,
+    flow: String,
+    step: usize,
+}
+
+impl super::fark::Fark {
+    pub fn with_flow(self, name: impl Into<String>, flow: AuthFlow) -> Self {
+        write(&self.flows).insert(name.into(), flow);
+        self
+    }
+
+    /// Runs the first step of the named flow with `strategy`.
+    pub async fn start_flow(
+        &self,
+        flow: &str,
+        strategy: &str,
+        input: AuthInput,
+    ) -> Result<FlowStep, AuthError> {
+        self.start_flow_with_context(flow, strategy, input, AuthContext::default())
+            .await
+    }
+
+    /// Like [`start_flow`](Self::start_flow), passing request metadata to the step's
+    /// strategy, the [`Throttle`](crate::Throttle) and emitted events.
+    pub async fn start_flow_with_context(
+        &self,
+        flow: &str,
+        strategy: &str,
+        input: AuthInput,
+        ctx: AuthContext,
+    ) -> Result<FlowStep, AuthError> {
+        if !read(&self.flows).contains_key(flow) {
+            return Err(AuthError::FlowNotFound);
+        }
+        self.advance_flow(flow, 0, None, strategy, input, ctx).await
+    }
+
+    /// Runs the next step of the flow recorded in `pending_token`. Once the step
+    /// succeeds the token is used up. Used tokens are recorded in the
+    /// [revocation store](Self::with_revocation_store) if there is one, else in memory,
+    /// so configure one when running more than one instance.
+    pub async fn continue_flow(
+        &self,
+        pending_token: &str,
+        strategy: &str,
+        input: AuthInput,
+    ) -> Result<FlowStep, AuthError> {
+        self.continue_flow_with_context(pending_token, strategy, input, AuthContext::default())
+            .await
+    }
+
+    /// Like [`continue_flow`](Self::continue_flow), passing request metadata to the
+    /// step's strategy, the [`Throttle`](crate::Throttle) and emitted events.
+    pub async fn continue_flow_with_context(
+        &self,
+        pending_token: &str,
+        strategy: &str,
+        input: AuthInput,
+        ctx: AuthContext,
+    ) -> Result<FlowStep, AuthError> {
+        let claims: PendingClaims = self.decode_token(pending_token)?;
+        if claims.typ != MFA_PENDING {
+            return Err(AuthError::InvalidToken);
+        }
+        let store = self.used_pending_tokens();
+        if store.is_revoked(&claims.jti).await? {
+            return Err(AuthError::InvalidToken);
+        }
+        let flow = claims.flow.clone();
+        let step = claims.step;
+        let (jti, expires_at) = (claims.jti.clone(), claims.exp);
+        let result = self
+            .advance_flow(&flow, step, Some(claims), strategy, input, ctx)
+            .await?;
+        store.revoke(jti, expires_at).await?;
+        Ok(result)
+    }
+
+    fn used_pending_tokens(&self) -> Arc<dyn RevocationStore> {
+        self.revocation_store()
+            .unwrap_or_else(|| self.used_pending_tokens.clone())
+    }
+
+    async fn advance_flow(
+        &self,
+        name: &str,
+        step: usize,
+        pending: Option<PendingClaims>,
+        strategy: &str,
+        input: AuthInput,
+        ctx: AuthContext,
+    ) -> Result<FlowStep, AuthError> {
+        let flow = read(&self.flows)
+            .get(name)
+            .cloned()
+            .ok_or(AuthError::InvalidToken)?;
+        let allowed = flow.steps.get(step).ok_or(AuthError::StepNotAllowed)?;
+        if !allowed.iter().any(|s| s == strategy) {
+            return Err(AuthError::StepNotAllowed);
+        }
+
+        let identity = self.authenticate_with_context(strategy, input, ctx).await?;
+        let (user_id, data, mut amr) = match pending {
+            Some(claims) if claims.sub != identity.user_id => return Err(AuthError::UserError),
+            Some(claims) => (claims.sub, claims.extra, claims.amr),
+            None => (identity.user_id, identity.data, Vec::new()),
+        };
+        for method in identity.amr {
+            if !amr.contains(&method) {
+                amr.push(method);
+            }
+        }
+
+        let next = step + 1;
+        if next == flow.steps.len() {
+            if flow.steps.len() > 1 {
+                amr.push("mfa".to_string());
+            }
+            return Ok(FlowStep::Complete(Identity {
+                user_id,
+                data,
+                amr,
+                auth_time: None,
+            }));
+        }
+
+        let issued_at = now()?;
+        let claims = PendingClaims {
+            exp: issued_at
+                .checked_add(flow.pending_ttl)
+                .ok_or_else(|| AuthError::internal("flow pending_ttl is out of range"))?,
+            iat: issued_at,
+            sub: user_id,
+            extra: data,
+            amr,
+            typ: MFA_PENDING.to_string(),
+            jti: URL_SAFE_NO_PAD.encode(random_bytes(16)),
+            flow: name.to_string(),
+            step: next,
+        };
+
+        Ok(FlowStep::Pending {
+            token: self.encode_token(&claims)?,
+            next: flow.steps[next].clone(),
+        })
+    }
+}
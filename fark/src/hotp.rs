@@ -0,0 +1,149 @@
+use crate::config::ConfigError;
+use crate::error::AuthError;
+use crate::strategy::BoxFuture;
+use crate::totp::{TotpAlgorithm, TotpSecret};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+
+/// RFC 4226 dynamic truncation of `HMAC(secret, counter)` into a `digits` long code.
+pub(crate) fn hotp_code(
+    algorithm: TotpAlgorithm,
+    secret: &TotpSecret,
+    counter: u64,
+    digits: u32,
+) -> String {
+    let hash = algorithm.hmac(secret.as_bytes(), &counter.to_be_bytes());
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    let code = binary as u64 % 10u64.pow(digits);
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+/// Checks a TOTP or HOTP code length: at least 6 digits (RFC 4226), at most the 9
+/// the 31-bit truncated HMAC can fill.
+pub(crate) fn code_digits(field: &str, digits: u32) -> Result<u32, ConfigError> {
+    if (6..=9).contains(&digits) {
+        Ok(digits)
+    } else {
+        Err(ConfigError::field(field, "must be between 6 and 9"))
+    }
+}
+
+/// RFC 4226 counter-based one-time passwords, as produced by hardware tokens.
+#[derive(Debug, Clone)]
+pub struct Hotp {
+    algorithm: TotpAlgorithm,
+    digits: u32,
+    look_ahead: u64,
+}
+
+impl Default for Hotp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hotp {
+    /// SHA1, 6 digits, and a look-ahead window of 10 counters to resynchronise
+    /// tokens whose button was pressed without logging in.
+    pub fn new() -> Self {
+        Self {
+            algorithm: TotpAlgorithm::Sha1,
+            digits: 6,
+            look_ahead: 10,
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: TotpAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Code length, 6 to 9 digits.
+    pub fn digits(mut self, digits: u32) -> Result<Self, ConfigError> {
+        self.digits = code_digits("hotp.digits", digits)?;
+        Ok(self)
+    }
+
+    pub fn look_ahead(mut self, window: u64) -> Self {
+        self.look_ahead = window;
+        self
+    }
+
+    pub fn generate(&self, secret: &TotpSecret, counter: u64) -> String {
+        hotp_code(self.algorithm, secret, counter, self.digits)
+    }
+
+    /// Checks `code` against `counter` and the look-ahead window. On success returns
+    /// the counter value to store, one past the counter that matched.
+    pub fn verify(&self, secret: &TotpSecret, code: &str, counter: u64) -> Option<u64> {
+        (counter..=counter.saturating_add(self.look_ahead))
+            .find(|c| bool::from(self.generate(secret, *c).as_bytes().ct_eq(code.as_bytes())))
+            .map(|c| c + 1)
+    }
+}
+
+/// Persists the moving HOTP counter for each account.
+pub trait HotpStore: Send + Sync {
+    /// Next expected counter for `account`; zero for a freshly enrolled token.
+    fn counter(&self, account: &str) -> BoxFuture<'_, Result<u64, AuthError>>;
+
+    /// Moves the counter from `current` to `next`, returning `false` if another
+    /// request already moved it. Implementations must perform this atomically.
+    fn advance(
+        &self,
+        account: &str,
+        current: u64,
+        next: u64,
+    ) -> BoxFuture<'_, Result<bool, AuthError>>;
+}
+
+/// Process-local [`HotpStore`], for tests and single-instance deployments.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryHotpStore {
+    counters: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl InMemoryHotpStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl HotpStore for InMemoryHotpStore {
+    fn counter(&self, account: &str) -> BoxFuture<'_, Result<u64, AuthError>> {
+        let counter = self
+            .counters
+            .lock()
+            .map(|counters| counters.get(account).copied().unwrap_or(0))
+            .map_err(AuthError::from);
+        Box::pin(async move { counter })
+    }
+
+    fn advance(
+        &self,
+        account: &str,
+        current: u64,
+        next: u64,
+    ) -> BoxFuture<'_, Result<bool, AuthError>> {
+        let advanced = self
+            .counters
+            .lock()
+            .map(|mut counters| {
+                let stored = counters.entry(account.to_string()).or_insert(0);
+                if *stored != current {
+                    return false;
+                }
+                *stored = next;
+                true
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { advanced })
+    }
+}
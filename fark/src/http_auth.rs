@@ -0,0 +1,257 @@
+//! `Authorization: Basic` and `Authorization: Digest` credentials (RFC 7617, RFC 7616),
+//! independent of any web framework.
+
+use crate::config::ConfigError;
+use crate::crypto::{hex, random_bytes};
+use crate::error::AuthError;
+use crate::input::AuthInput;
+use crate::secret::Secret;
+use crate::time::now;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::Algorithm;
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use subtle::ConstantTimeEq;
+
+/// Splits an `Authorization` header value into its scheme and credentials.
+fn credentials<'a>(header: &'a str, scheme: &str) -> Result<&'a str, AuthError> {
+    let (found, credentials) = header
+        .trim()
+        .split_once(' ')
+        .ok_or(AuthError::InvalidInput)?;
+    if !found.eq_ignore_ascii_case(scheme) {
+        return Err(AuthError::InvalidInput);
+    }
+    Ok(credentials.trim())
+}
+
+/// Parses `Basic <base64(username:password)>` into the `AuthInput::Local` map taken by
+/// [`Fark::with_local`](crate::Fark::with_local), with `username` and `password` keys.
+pub fn parse_basic(header: &str) -> Result<AuthInput, AuthError> {
+    let decoded = STANDARD
+        .decode(credentials(header, "Basic")?)
+        .map_err(|_| AuthError::InvalidInput)?;
+    let decoded = String::from_utf8(decoded).map_err(|_| AuthError::InvalidInput)?;
+    let (username, password) = decoded.split_once(':').ok_or(AuthError::InvalidInput)?;
+
+    let mut data = HashMap::new();
+    data.insert("username".to_string(), username.to_string());
+    data.insert("password".to_string(), password.to_string());
+    Ok(AuthInput::Local { data })
+}
+
+/// `WWW-Authenticate` value asking for Basic credentials.
+pub fn basic_challenge(realm: &str) -> String {
+    format!("Basic realm=\"{}\", charset=\"UTF-8\"", quote(realm))
+}
+
+/// The parameters of an `Authorization: Digest` header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DigestCredentials {
+    pub username: String,
+    pub realm: String,
+    pub nonce: String,
+    pub uri: String,
+    pub response: String,
+    pub algorithm: Option<String>,
+    pub qop: Option<String>,
+    pub nc: Option<String>,
+    pub cnonce: Option<String>,
+    pub opaque: Option<String>,
+}
+
+impl DigestCredentials {
+    pub fn parse(header: &str) -> Result<Self, AuthError> {
+        let params = parse_params(credentials(header, "Digest")?)?;
+        let required = |name: &str| params.get(name).cloned().ok_or(AuthError::InvalidInput);
+        Ok(Self {
+            username: required("username")?,
+            realm: required("realm")?,
+            nonce: required("nonce")?,
+            uri: required("uri")?,
+            response: required("response")?,
+            algorithm: params.get("algorithm").cloned(),
+            qop: params.get("qop").cloned(),
+            nc: params.get("nc").cloned(),
+            cnonce: params.get("cnonce").cloned(),
+            opaque: params.get("opaque").cloned(),
+        })
+    }
+}
+
+/// Parses `key=value, key="quoted, value"` pairs. Keys are lowercased.
+fn parse_params(input: &str) -> Result<HashMap<String, String>, AuthError> {
+    let mut params = HashMap::new();
+    let mut rest = input.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=').ok_or(AuthError::InvalidInput)?;
+        let after = after.trim_start();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next().ok_or(AuthError::InvalidInput)? {
+                        (_, '\\') => value.extend(chars.next().map(|(_, c)| c)),
+                        (i, '"') => break i,
+                        (_, c) => value.push(c),
+                    }
+                };
+                (value, &quoted[end + 1..])
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        params.insert(key.trim().to_ascii_lowercase(), value);
+        rest = after.trim_start().trim_start_matches(',').trim_start();
+    }
+    Ok(params)
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn sha256_hex(input: &str) -> String {
+    hex(&Sha256::digest(input.as_bytes()))
+}
+
+/// Server side of Digest authentication with the SHA-256 algorithms of RFC 7616.
+/// MD5 is not supported.
+///
+/// Nonces are stateless: a timestamp signed with `secret`, valid for `nonce_ttl`
+/// seconds. Neither nonces nor `nc` counts are recorded, so a captured
+/// `Authorization: Digest` header can be replayed for the same method and URI until
+/// its nonce expires; keep `nonce_ttl` short and serve Digest over TLS only. Digest
+/// also needs each user's password in a recoverable form, so prefer Basic over TLS
+/// where possible.
+#[derive(Debug, Clone)]
+pub struct DigestAuth {
+    realm: String,
+    secret: Secret,
+    nonce_ttl: u64,
+}
+
+impl DigestAuth {
+    /// `secret` signs the nonces and must pass the same strength check as an `HS256`
+    /// JWT secret.
+    pub fn new(realm: impl Into<String>, secret: impl Into<Secret>) -> Result<Self, ConfigError> {
+        let secret = secret.into();
+        secret
+            .check_hmac_strength(Algorithm::HS256)
+            .map_err(|message| ConfigError::field("digest.secret", message))?;
+        Ok(Self {
+            realm: realm.into(),
+            secret,
+            nonce_ttl: 300,
+        })
+    }
+
+    /// Lifetime of a nonce, and so the window in which a captured header can be
+    /// replayed. Defaults to 5 minutes.
+    pub fn nonce_ttl(mut self, secs: u64) -> Self {
+        self.nonce_ttl = secs;
+        self
+    }
+
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    /// `WWW-Authenticate` value with a fresh nonce. `stale` tells the client its
+    /// previous nonce expired and it may retry without asking the user again.
+    pub fn challenge(&self, stale: bool) -> Result<String, AuthError> {
+        let nonce = self.sign_nonce(now()?, &hex(&random_bytes(8)));
+        Ok(format!(
+            "Digest realm=\"{}\", qop=\"auth\", algorithm=SHA-256, nonce=\"{nonce}\"{}",
+            quote(&self.realm),
+            if stale { ", stale=true" } else { "" }
+        ))
+    }
+
+    fn sign_nonce(&self, issued_at: u64, salt: &str) -> String {
+        let payload = format!("{issued_at}.{salt}");
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.expose().as_bytes())
+            .expect("hmac accepts any key");
+        mac.update(payload.as_bytes());
+        format!("{payload}.{}", hex(&mac.finalize().into_bytes()))
+    }
+
+    fn check_nonce(&self, nonce: &str) -> Result<(), AuthError> {
+        let mut parts = nonce.splitn(3, '.');
+        let (Some(issued_at), Some(salt), Some(_)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AuthError::InvalidToken);
+        };
+        let issued_at: u64 = issued_at.parse().map_err(|_| AuthError::InvalidToken)?;
+        if !bool::from(
+            self.sign_nonce(issued_at, salt)
+                .as_bytes()
+                .ct_eq(nonce.as_bytes()),
+        ) {
+            return Err(AuthError::InvalidToken);
+        }
+        let expires_at = issued_at
+            .checked_add(self.nonce_ttl)
+            .ok_or_else(|| AuthError::internal("digest nonce_ttl is out of range"))?;
+        if expires_at <= now()? {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(())
+    }
+
+    /// Checks `credentials` sent with a `method` request against the user's password.
+    /// Fails with [`AuthError::InvalidToken`] for a forged or expired nonce, which
+    /// should be answered with a `stale` challenge, and
+    /// [`AuthError::PasswordMismatch`] for a wrong response.
+    ///
+    /// The caller must also check that `credentials.uri` is the requested URI.
+    pub fn verify(
+        &self,
+        credentials: &DigestCredentials,
+        method: &str,
+        password: &str,
+    ) -> Result<(), AuthError> {
+        let sess = match credentials.algorithm.as_deref() {
+            Some(algorithm) if algorithm.eq_ignore_ascii_case("SHA-256") => false,
+            Some(algorithm) if algorithm.eq_ignore_ascii_case("SHA-256-sess") => true,
+            _ => return Err(AuthError::InvalidInput),
+        };
+        if credentials.realm != self.realm {
+            return Err(AuthError::InvalidInput);
+        }
+        self.check_nonce(&credentials.nonce)?;
+
+        let mut ha1 = sha256_hex(&format!(
+            "{}:{}:{password}",
+            credentials.username, self.realm
+        ));
+        let ha2 = sha256_hex(&format!("{method}:{}", credentials.uri));
+        let expected = match (&credentials.qop, &credentials.nc, &credentials.cnonce) {
+            (Some(qop), Some(nc), Some(cnonce)) if qop == "auth" => {
+                if sess {
+                    ha1 = sha256_hex(&format!("{ha1}:{}:{cnonce}", credentials.nonce));
+                }
+                sha256_hex(&format!(
+                    "{ha1}:{}:{nc}:{cnonce}:{qop}:{ha2}",
+                    credentials.nonce
+                ))
+            }
+            (None, _, _) if !sess => sha256_hex(&format!("{ha1}:{}:{ha2}", credentials.nonce)),
+            _ => return Err(AuthError::InvalidInput),
+        };
+
+        if !bool::from(
+            expected
+                .as_bytes()
+                .ct_eq(credentials.response.to_ascii_lowercase().as_bytes()),
+        ) {
+            return Err(AuthError::PasswordMismatch);
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,62 @@
-
 use serde_json::Value;
 
-#[derive(Debug, Clone)]
+/// Who was authenticated. Build one with [`Identity::new`] and the `with_*` setters;
+/// new fields may be added without a breaking release.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
 pub struct Identity {
     pub user_id: String,
     pub data: Value,
+    /// Authentication methods (RFC 8176 `amr` values) used to establish this identity.
+    pub amr: Vec<String>,
+    /// When the user actually signed in, as a Unix timestamp. Carried through
+    /// access tokens, so an identity verified from a session token keeps the time of
+    /// the original login rather than the time the token was checked.
+    pub auth_time: Option<u64>,
 }
 
 impl Identity {
+    pub fn new(user_id: impl Into<String>, data: Value) -> Self {
+        Self {
+            user_id: user_id.into(),
+            data,
+            amr: Vec::new(),
+            auth_time: None,
+        }
+    }
+
+    /// Sets the authentication methods, replacing any the strategy name would imply.
+    pub fn with_amr<I, S>(mut self, amr: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.amr = amr.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets when the user signed in, as a Unix timestamp.
+    pub fn with_auth_time(mut self, auth_time: u64) -> Self {
+        self.auth_time = Some(auth_time);
+        self
+    }
+
     pub fn data(&self) -> &Value {
         &self.data
     }
-}
\ No newline at end of file
+
+    /// Scopes granted to an OAuth access token or API key, from the space-separated
+    /// `scope` field of the data. Empty for first-party tokens.
+    pub fn scopes(&self) -> Vec<&str> {
+        self.data
+            .get("scope")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect()
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes().contains(&scope)
+    }
+}
@@ -1,6 +1,7 @@
+use crate::http_auth::DigestCredentials;
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AuthInput {
     Local {
         data: HashMap<String, String>,
@@ -14,4 +15,94 @@ pub enum AuthInput {
     Pin {
         pin_code: i32,
     },
-}
\ No newline at end of file
+    Totp {
+        account: String,
+        code: String,
+    },
+    Hotp {
+        account: String,
+        code: String,
+    },
+    RecoveryCode {
+        account: String,
+        code: String,
+    },
+    Otp {
+        account: String,
+        code: String,
+    },
+    MagicLink {
+        token: String,
+    },
+    /// A WebAuthn assertion; binary fields are base64url encoded as sent by the browser.
+    WebAuthn {
+        credential_id: String,
+        client_data_json: String,
+        authenticator_data: String,
+        signature: String,
+    },
+    /// An API key issued by [`ApiKeys::issue`](crate::ApiKeys::issue).
+    ApiKey {
+        key: String,
+    },
+    /// An `Authorization: Digest` header sent with a `method` request.
+    Digest {
+        credentials: DigestCredentials,
+        method: String,
+    },
+    /// An opaque bearer token from another issuer, checked by
+    /// [`RemoteIntrospection`](crate::RemoteIntrospection).
+    BearerToken {
+        token: String,
+    },
+    /// A base64 `SAMLResponse` posted to the assertion consumer service, with the id
+    /// of the AuthnRequest it answers (`None` if IdP-initiated).
+    Saml {
+        response: String,
+        request_id: Option<String>,
+    },
+}
+
+impl AuthInput {
+    /// Name of the built-in strategy that accepts this input, used by
+    /// [`Fark::authenticate_auto`](crate::Fark::authenticate_auto).
+    pub fn strategy_name(&self) -> &'static str {
+        match self {
+            AuthInput::Local { .. } => "local",
+            AuthInput::Google { .. } => "google",
+            AuthInput::Pin { .. } => "pin",
+            AuthInput::Totp { .. } => "totp",
+            AuthInput::Hotp { .. } => "hotp",
+            AuthInput::RecoveryCode { .. } => "recovery_code",
+            AuthInput::Otp { .. } => "otp",
+            AuthInput::MagicLink { .. } => "magic_link",
+            AuthInput::WebAuthn { .. } => "webauthn",
+            AuthInput::ApiKey { .. } => "api_key",
+            AuthInput::Digest { .. } => "digest",
+            AuthInput::BearerToken { .. } => "introspection",
+            AuthInput::Saml { .. } => "saml",
+        }
+    }
+
+    /// The account the input claims to authenticate, used to key rate limiting.
+    pub fn account(&self) -> Option<&str> {
+        match self {
+            AuthInput::Local { data } => data
+                .get("username")
+                .or_else(|| data.get("email"))
+                .map(String::as_str),
+            AuthInput::Totp { account, .. }
+            | AuthInput::Hotp { account, .. }
+            | AuthInput::RecoveryCode { account, .. }
+            | AuthInput::Otp { account, .. } => Some(account),
+            AuthInput::WebAuthn { credential_id, .. } => Some(credential_id),
+            AuthInput::Digest { credentials, .. } => Some(&credentials.username),
+            AuthInput::Google { .. }
+            | AuthInput::Pin { .. }
+            | AuthInput::MagicLink { .. }
+            | AuthInput::ApiKey { .. }
+            | AuthInput::BearerToken { .. }
+            | AuthInput::Saml { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Token introspection (RFC 7662) and revocation (RFC 7009) of fark access tokens,
+//! independent of any web framework.
+
+use crate::error::AuthError;
+use crate::events::{AuthEvent, AuthEventKind};
+use crate::fark::{Fark, read, write};
+use crate::identity::Identity;
+use crate::jwt::{Claims, OAUTH_ACCESS_TYP};
+use crate::oauth_server::{OAuthError, basic_client_credentials};
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Remembers the ids (`jti`) of revoked access tokens until the tokens expire.
+pub trait RevocationStore: Send + Sync {
+    fn revoke(&self, jti: String, expires_at: u64) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    fn is_revoked(&self, jti: &str) -> BoxFuture<'_, Result<bool, AuthError>>;
+}
+
+/// Process-local [`RevocationStore`], for tests and single-instance deployments.
+/// Expired entries are pruned on every revocation.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRevocationStore {
+    revoked: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl InMemoryRevocationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RevocationStore for InMemoryRevocationStore {
+    fn revoke(&self, jti: String, expires_at: u64) -> BoxFuture<'_, Result<(), AuthError>> {
+        let revoked = now().map_err(AuthError::from).and_then(|current| {
+            let mut revoked = self.revoked.lock()?;
+            revoked.retain(|_, expires_at| *expires_at > current);
+            revoked.insert(jti, expires_at);
+            Ok(())
+        });
+        Box::pin(async move { revoked })
+    }
+
+    fn is_revoked(&self, jti: &str) -> BoxFuture<'_, Result<bool, AuthError>> {
+        let revoked = self
+            .revoked
+            .lock()
+            .map(|revoked| revoked.contains_key(jti))
+            .map_err(AuthError::from);
+        Box::pin(async move { revoked })
+    }
+}
+
+/// Form parameters of the introspection and revocation endpoints. Client
+/// credentials are only used by [`AuthorizationServer::revoke`](crate::AuthorizationServer::revoke).
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TokenHintRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+impl TokenHintRequest {
+    /// Fills `client_id` and `client_secret` from a Basic `Authorization` header.
+    pub fn basic_credentials(mut self, header: &str) -> Result<Self, OAuthError> {
+        let (client_id, client_secret) = basic_client_credentials(header)?;
+        self.client_id = Some(client_id);
+        self.client_secret = Some(client_secret);
+        Ok(self)
+    }
+}
+
+/// RFC 7662 introspection response. Inactive tokens carry nothing but `active`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// The identity data of the token.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub ext: Value,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub amr: Vec<String>,
+}
+
+impl IntrospectionResponse {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    fn active(claims: Claims) -> Self {
+        let field = |name: &str| {
+            claims
+                .extra
+                .get(name)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        Self {
+            active: true,
+            scope: field("scope"),
+            client_id: field("client_id"),
+            token_type: Some("Bearer".to_string()),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            sub: Some(claims.sub.clone()),
+            aud: claims.aud.clone(),
+            iss: claims.iss.clone(),
+            jti: claims.jti.clone(),
+            ext: claims.extra,
+            amr: claims.amr,
+        }
+    }
+}
+
+impl Fark {
+    /// Checks [`verify_active_jwt`](Self::verify_active_jwt) against `store`, and
+    /// lets [`revoke_jwt`](Self::revoke_jwt) record revocations in it.
+    pub fn with_revocation_store<S>(self, store: S) -> Self
+    where
+        S: RevocationStore + 'static,
+    {
+        *write(&self.revocations) = Some(Arc::new(store));
+        self
+    }
+
+    /// Like [`verify_jwt`](Self::verify_jwt), but also rejects tokens revoked in the
+    /// [revocation store](Self::with_revocation_store). Prefer it over `verify_jwt`
+    /// whenever a store is configured.
+    pub async fn verify_active_jwt(&self, token: String) -> Result<Identity, AuthError> {
+        let identity = self.verify_jwt(token.clone())?;
+        if let Err(err) = self.active_claims(&token).await {
+            self.emit(AuthEvent::new(AuthEventKind::TokenRejected, Err(&err)));
+            return Err(err);
+        }
+        Ok(identity)
+    }
+
+    /// Verifies an access token issued to a third-party client by the
+    /// [`AuthorizationServer`](crate::AuthorizationServer), for resource servers.
+    /// Revoked tokens and first-party tokens are rejected, and the token must have
+    /// been granted every one of `scopes`, else [`AuthError::InsufficientScope`].
+    pub async fn verify_oauth_access_token(
+        &self,
+        token: &str,
+        scopes: &[&str],
+    ) -> Result<Identity, AuthError> {
+        let result = self.oauth_access_identity(token, scopes).await;
+        if let Err(err) = &result {
+            self.emit(AuthEvent::new(AuthEventKind::TokenRejected, Err(err)));
+        }
+        result
+    }
+
+    async fn oauth_access_identity(
+        &self,
+        token: &str,
+        scopes: &[&str],
+    ) -> Result<Identity, AuthError> {
+        let claims = self.active_claims(token).await?;
+        if claims.typ.as_deref() != Some(OAUTH_ACCESS_TYP) {
+            return Err(AuthError::InvalidToken);
+        }
+        let identity = Identity::from(claims);
+        if !scopes.iter().all(|scope| identity.has_scope(scope)) {
+            return Err(AuthError::InsufficientScope);
+        }
+        Ok(identity)
+    }
+
+    /// Revokes an access token until it expires. Invalid and expired tokens are
+    /// ignored, as RFC 7009 asks; without a revocation store this fails with
+    /// [`AuthError::InternalError`].
+    pub async fn revoke_jwt(&self, token: &str) -> Result<(), AuthError> {
+        let store = self.revocation_store().ok_or_else(|| {
+            AuthError::internal("revocation store not configured, see Fark::with_revocation_store")
+        })?;
+        match self.access_token_claims(token) {
+            Ok(Claims {
+                jti: Some(jti),
+                exp,
+                ..
+            }) => store.revoke(jti, exp).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// RFC 7662 introspection of an access token. Invalid, expired and revoked
+    /// tokens are reported as inactive; only store failures are errors.
+    pub async fn introspect(&self, token: &str) -> Result<IntrospectionResponse, AuthError> {
+        match self.active_claims(token).await {
+            Ok(claims) => Ok(IntrospectionResponse::active(claims)),
+            Err(err) if err.is_transient() => Err(err),
+            Err(_) => Ok(IntrospectionResponse::inactive()),
+        }
+    }
+
+    async fn active_claims(&self, token: &str) -> Result<Claims, AuthError> {
+        let claims = self.access_token_claims(token)?;
+        if let (Some(store), Some(jti)) = (self.revocation_store(), &claims.jti)
+            && store.is_revoked(jti).await?
+        {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims)
+    }
+
+    pub(crate) fn revocation_store(&self) -> Option<Arc<dyn RevocationStore>> {
+        read(&self.revocations).clone()
+    }
+}
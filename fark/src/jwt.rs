@@ -1,73 +1,361 @@
+use crate::config::ConfigError;
+use crate::crypto::random_bytes;
 use crate::error::AuthError;
+use crate::events::{AuthEvent, AuthEventKind};
+use crate::fark::read;
 use crate::identity::Identity;
+use crate::secret::Secret;
+use crate::telemetry;
 use crate::time::now;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Claims {
+pub(crate) struct Claims {
     #[serde(skip_serializing_if = "Option::is_none")]
-    aud: Option<String>, // Optional. Audience
-    exp: u64, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
-    iat: u64, // Optional. Issued at (as UTC timestamp)
+    pub(crate) aud: Option<String>, // Optional. Audience
+    pub(crate) exp: u64, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
+    pub(crate) iat: u64, // Optional. Issued at (as UTC timestamp)
     #[serde(skip_serializing_if = "Option::is_none")]
-    iss: Option<String>, // Optional. Issuer
-    sub: String,
-    extra: Value,
+    pub(crate) iss: Option<String>, // Optional. Issuer
+    pub(crate) sub: String,
+    pub(crate) extra: Value,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) amr: Vec<String>, // Authentication methods used to establish the identity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) typ: Option<String>, // Set on special-purpose tokens that must not be accepted as access tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) jti: Option<String>, // Token id, the handle used to revoke the token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) auth_time: Option<u64>, // When the user signed in, kept when the token is re-issued
 }
+/// `typ` of access tokens the [`AuthorizationServer`](crate::AuthorizationServer)
+/// issues to third-party clients. They are only accepted by
+/// [`Fark::verify_oauth_access_token`](crate::Fark::verify_oauth_access_token).
+pub(crate) const OAUTH_ACCESS_TYP: &str = "oauth_access";
+
+impl From<Claims> for Identity {
+    fn from(claims: Claims) -> Self {
+        Identity {
+            user_id: claims.sub,
+            data: claims.extra,
+            amr: claims.amr,
+            auth_time: claims.auth_time.or(Some(claims.iat)),
+        }
+    }
+}
+
 impl super::fark::Fark {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "fark.issue_jwt",
+            skip_all,
+            fields(subject = %identity.user_id, error = tracing::field::Empty)
+        )
+    )]
     pub fn issue_jwt(&self, identity: Identity, ttl_secs: u64) -> Result<String, AuthError> {
-        let secret = self.secret.clone();
+        self.issue_access_token(identity, ttl_secs, None)
+    }
+
+    /// Issues an access token for a third-party client, which first-party checks
+    /// such as [`verify_jwt`](Self::verify_jwt) reject.
+    pub(crate) fn issue_oauth_access_token(
+        &self,
+        identity: Identity,
+        ttl_secs: u64,
+    ) -> Result<String, AuthError> {
+        self.issue_access_token(identity, ttl_secs, Some(OAUTH_ACCESS_TYP))
+    }
+
+    fn issue_access_token(
+        &self,
+        identity: Identity,
+        ttl_secs: u64,
+        typ: Option<&str>,
+    ) -> Result<String, AuthError> {
+        let subject = identity.user_id.clone();
+        let result = self.sign_access_token(identity, ttl_secs, typ);
+        telemetry::record_outcome(None, &result);
+
+        let mut event = AuthEvent::new(AuthEventKind::TokenIssued, result.as_ref().map(|_| ()));
+        event.subject = Some(subject);
+        self.emit(event);
+
+        result
+    }
+
+    /// Verifies the signature, expiry, issuer and audience of a first-party access
+    /// token.
+    ///
+    /// This check is stateless: it does **not** consult the revocation store, so a
+    /// token revoked with [`revoke_jwt`](Self::revoke_jwt) still passes until it
+    /// expires. Use [`verify_active_jwt`](Self::verify_active_jwt), as the `FarkAuth`
+    /// middleware does, wherever revocation must take effect.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "fark.verify_jwt",
+            skip_all,
+            fields(subject = tracing::field::Empty, error = tracing::field::Empty)
+        )
+    )]
+    pub fn verify_jwt(&self, token: String) -> Result<Identity, AuthError> {
+        let result = self.verify_access_token(&token);
+        telemetry::record_verify(&result);
+        telemetry::record_outcome(
+            result
+                .as_ref()
+                .ok()
+                .map(|identity| identity.user_id.as_str()),
+            &result,
+        );
 
-        if secret.is_empty() {
-            return Err(AuthError::SecretNotFound);
+        if let Err(err) = &result {
+            self.emit(AuthEvent::new(AuthEventKind::TokenRejected, Err(err)));
         }
 
-        let issued_at = now().map_err(|_| AuthError::InternalError)?;
-        let expires_at = issued_at + ttl_secs;
+        result
+    }
+
+    /// Records the end of a session for `token`. Tokens are stateless, so it stays
+    /// valid until it expires; use [`revoke_jwt`](Self::revoke_jwt) to reject it
+    /// before then.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "fark.logout",
+            skip_all,
+            fields(subject = tracing::field::Empty, error = tracing::field::Empty)
+        )
+    )]
+    pub fn logout(&self, token: &str) -> Result<(), AuthError> {
+        let result = self.verify_access_token(token);
+        telemetry::record_outcome(
+            result
+                .as_ref()
+                .ok()
+                .map(|identity| identity.user_id.as_str()),
+            &result,
+        );
+
+        let mut event = AuthEvent::new(AuthEventKind::Logout, result.as_ref().map(|_| ()));
+        event.subject = result
+            .as_ref()
+            .ok()
+            .map(|identity| identity.user_id.clone());
+        self.emit(event);
+
+        result.map(|_| ())
+    }
+
+    fn sign_access_token(
+        &self,
+        identity: Identity,
+        ttl_secs: u64,
+        typ: Option<&str>,
+    ) -> Result<String, AuthError> {
+        let issued_at = now()?;
+        let expires_at = issued_at
+            .checked_add(ttl_secs)
+            .ok_or_else(|| AuthError::internal("jwt ttl_secs is out of range"))?;
 
+        let (audience, issuer) = {
+            let jwt = read(&self.jwt);
+            (jwt.audience.clone(), jwt.issuer.clone())
+        };
         let my_claims = Claims {
             sub: identity.user_id,
             iat: issued_at,
             exp: expires_at,
-            aud: None,
-            iss: None,
+            aud: audience,
+            iss: issuer,
             extra: identity.data,
+            amr: identity.amr,
+            auth_time: Some(identity.auth_time.unwrap_or(issued_at)),
+            typ: typ.map(str::to_string),
+            jti: Some(URL_SAFE_NO_PAD.encode(random_bytes(16))),
         };
 
-        let token = encode(
-            &Header::new(Algorithm::HS256),
-            &my_claims,
-            &EncodingKey::from_secret(secret.as_bytes()),
-        )
-        .map_err(|_| AuthError::InternalError)?;
+        self.encode_token(&my_claims)
+    }
 
-        Ok(token)
+    fn verify_access_token(&self, token: &str) -> Result<Identity, AuthError> {
+        let claims = self.access_token_claims(token)?;
+        if claims.typ.is_some() {
+            return Err(AuthError::InvalidToken);
+        }
+
+        Ok(claims.into())
     }
 
-    pub fn verify_jwt(&self, token: String) -> Result<Identity, AuthError> {
-        let secret = self.secret.clone();
-        if secret.is_empty() {
-            return Err(AuthError::SecretNotFound);
+    /// Decodes and checks a first-party or OAuth access token, keeping its
+    /// registered claims. Callers decide which of the two they accept.
+    pub(crate) fn access_token_claims(&self, token: &str) -> Result<Claims, AuthError> {
+        let claims: Claims = self.decode_token(token)?;
+
+        if claims
+            .typ
+            .as_deref()
+            .is_some_and(|typ| typ != OAUTH_ACCESS_TYP)
+        {
+            return Err(AuthError::InvalidToken);
+        }
+        // Checked here rather than in `decode_token` so internal tokens, which carry
+        // no issuer or audience, still decode.
+        let jwt = read(&self.jwt);
+        if jwt.issuer.is_some() && claims.iss != jwt.issuer {
+            return Err(AuthError::InvalidToken);
         }
+        if jwt.audience.is_some() && claims.aud != jwt.audience {
+            return Err(AuthError::InvalidToken);
+        }
+
+        Ok(claims)
+    }
+
+    /// Default access token lifetime, as configured by `jwt.ttl_secs`.
+    pub fn jwt_ttl(&self) -> u64 {
+        read(&self.jwt).ttl_secs
+    }
+
+    pub(crate) fn encode_token<T: Serialize>(&self, claims: &T) -> Result<String, AuthError> {
+        let jwt = read(&self.jwt);
+        let token = encode(&Header::new(jwt.algorithm), claims, jwt.encoding_key()?)
+            .map_err(AuthError::internal)?;
 
-        let mut validation = Validation::new(Algorithm::HS256);
+        Ok(token)
+    }
+
+    pub(crate) fn decode_token<T: DeserializeOwned>(&self, token: &str) -> Result<T, AuthError> {
+        let jwt = read(&self.jwt);
+        let mut validation = Validation::new(jwt.algorithm);
         validation.validate_exp = true;
-        validation.leeway = 30;
+        validation.validate_aud = false;
+        validation.leeway = jwt.leeway_secs;
 
-        let token_data = decode::<Claims>(
-            &token,
-            &DecodingKey::from_secret(secret.as_bytes()),
-            &validation,
-        )
-        .map_err(|_| AuthError::InvalidToken)?;
+        let token_data = decode::<T>(token, jwt.decoding_key()?, &validation)
+            .map_err(|_| AuthError::InvalidToken)?;
 
-        let claims = token_data.claims;
+        Ok(token_data.claims)
+    }
+}
 
-        Ok(Identity {
-            user_id: claims.sub,
-            data: claims.extra,
-        })
+/// Signing configuration used for access tokens and fark's internal tokens.
+#[derive(Debug, Clone)]
+pub(crate) struct JwtSettings {
+    pub(crate) algorithm: Algorithm,
+    /// HMAC key for the `HS*` algorithms.
+    pub(crate) secret: Secret,
+    /// PEM keys for the RSA, ECDSA and EdDSA algorithms.
+    pub(crate) private_key_pem: Option<Secret>,
+    pub(crate) public_key_pem: Option<String>,
+    pub(crate) issuer: Option<String>,
+    pub(crate) audience: Option<String>,
+    pub(crate) ttl_secs: u64,
+    pub(crate) leeway_secs: u64,
+    /// Built once by [`build_keys`](Self::build_keys) whenever the key material
+    /// changes, so signing and verifying never copy the secret again.
+    pub(crate) keys: JwtKeys,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct JwtKeys {
+    encoding: Option<Arc<EncodingKey>>,
+    decoding: Option<Arc<DecodingKey>>,
+}
+
+impl fmt::Debug for JwtKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JwtKeys(..)")
+    }
+}
+
+impl Default for JwtSettings {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::HS256,
+            secret: Secret::default(),
+            private_key_pem: None,
+            public_key_pem: None,
+            issuer: None,
+            audience: None,
+            ttl_secs: 3600,
+            leeway_secs: 30,
+            keys: JwtKeys::default(),
+        }
+    }
+}
+
+impl JwtSettings {
+    pub(crate) fn encoding_key(&self) -> Result<&EncodingKey, AuthError> {
+        self.keys
+            .encoding
+            .as_deref()
+            .ok_or(AuthError::SecretNotFound)
+    }
+
+    pub(crate) fn decoding_key(&self) -> Result<&DecodingKey, AuthError> {
+        self.keys
+            .decoding
+            .as_deref()
+            .ok_or(AuthError::SecretNotFound)
+    }
+
+    /// Rebuilds the signing and verification keys from the current key material.
+    /// Missing material leaves the key unset; malformed material is an error.
+    pub(crate) fn build_keys(&mut self) -> Result<(), ConfigError> {
+        let keys = match self.algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                if self.secret.is_empty() {
+                    JwtKeys::default()
+                } else {
+                    let secret = self.secret.expose().as_bytes();
+                    JwtKeys {
+                        encoding: Some(Arc::new(EncodingKey::from_secret(secret))),
+                        decoding: Some(Arc::new(DecodingKey::from_secret(secret))),
+                    }
+                }
+            }
+            algorithm => {
+                let encoding = match &self.private_key_pem {
+                    Some(pem) => {
+                        let pem = pem.expose().as_bytes();
+                        let key = match algorithm {
+                            Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(pem),
+                            Algorithm::EdDSA => EncodingKey::from_ed_pem(pem),
+                            _ => EncodingKey::from_rsa_pem(pem),
+                        }
+                        .map_err(|err| {
+                            ConfigError::field("jwt.private_key_pem", err.to_string())
+                        })?;
+                        Some(Arc::new(key))
+                    }
+                    None => None,
+                };
+                let decoding = match &self.public_key_pem {
+                    Some(pem) => {
+                        let pem = pem.as_bytes();
+                        let key = match algorithm {
+                            Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(pem),
+                            Algorithm::EdDSA => DecodingKey::from_ed_pem(pem),
+                            _ => DecodingKey::from_rsa_pem(pem),
+                        }
+                        .map_err(|err| ConfigError::field("jwt.public_key_pem", err.to_string()))?;
+                        Some(Arc::new(key))
+                    }
+                    None => None,
+                };
+                JwtKeys { encoding, decoding }
+            }
+        };
+        self.keys = keys;
+        Ok(())
     }
 }
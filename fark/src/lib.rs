@@ -1,18 +1,101 @@
 //! Fark — A lightweight, pluggable authentication library for Rust.
 //!
-//! Provides strategy-based authentication and HMAC-SHA256 JWT support.
+//! Provides strategy-based authentication with multi-step flows, signed access
+//! tokens (HMAC, RSA, ECDSA or EdDSA JWTs) with revocation and introspection, and
+//! building blocks for second factors and federation:
+//!
+//! - one-time codes: TOTP, HOTP, emailed or texted codes, recovery codes
+//! - passwordless: WebAuthn passkeys and magic links
+//! - HTTP Basic, Digest and API keys
+//! - an OAuth 2.0 authorization server with OpenID Connect ID tokens and userinfo
+//! - SAML 2.0 single sign-on as a service provider (`saml` feature)
+//!
+//! Rate limiting and audit events are built in. Optional features add `tracing`
+//! spans, `metrics`, TOML and YAML configuration (`toml`, `yaml`), QR codes for
+//! TOTP enrollment (`qr`) and actix-web middleware and endpoints (`actix`).
 
+#[cfg(feature = "actix")]
+pub mod actix;
+pub mod api_key;
+mod cbor;
+pub mod config;
+pub mod context;
+mod crypto;
 pub mod error;
+pub mod events;
+pub mod execution;
 pub mod fark;
+pub mod flow;
+pub mod hotp;
+pub mod http_auth;
 pub mod identity;
 pub mod input;
+pub mod introspection;
 pub mod jwt;
+pub mod magic_link;
+pub mod oauth_server;
+pub mod oidc;
+pub mod otp;
+pub mod recovery;
+pub mod remote_introspection;
+#[cfg(feature = "saml")]
+pub mod saml;
+pub mod secret;
 pub mod strategy;
+mod telemetry;
+pub mod throttle;
 pub mod time;
+pub mod totp;
+pub mod webauthn;
 
+pub use api_key::{
+    ApiKeyRecord, ApiKeyStore, ApiKeys, InMemoryApiKeyStore, IssuedApiKey, hash_api_key,
+};
+pub use config::{ConfigError, FarkConfig, JwtConfig, OAuthProviderConfig, RateLimitConfig};
+pub use context::AuthContext;
 pub use error::*;
+pub use events::{AuthEvent, AuthEventHandler, AuthEventKind, AuthOutcome, JsonLinesAuditSink};
+pub use execution::{CircuitBreakerPolicy, ExecutionPolicy, RetryPolicy};
 pub use fark::Fark;
+pub use flow::{AuthFlow, FlowStep};
+pub use hotp::{Hotp, HotpStore, InMemoryHotpStore};
+pub use http_auth::{DigestAuth, DigestCredentials, basic_challenge, parse_basic};
 pub use identity::Identity;
 pub use input::AuthInput;
+pub use introspection::{
+    InMemoryRevocationStore, IntrospectionResponse, RevocationStore, TokenHintRequest,
+};
+pub use magic_link::{
+    Email, InMemoryMagicLinkStore, InMemoryMailer, MagicLink, MagicLinkStore, Mailer,
+};
+pub use oauth_server::{
+    AuthorizationCode, AuthorizationRequest, AuthorizationServer, ClientStore, GrantStore,
+    GrantType, InMemoryClientStore, InMemoryGrantStore, OAuthClient, OAuthError, OAuthErrorBody,
+    RefreshGrant, RegisteredClient, TokenRequest, TokenResponse,
+};
+pub use oidc::{DiscoveryDocument, userinfo_claims};
+pub use otp::{
+    CodeSender, InMemoryOtpStore, OneTimeCode, OtpChannel, OtpMessage, OtpStore,
+    RecordingCodeSender, StoredOtp,
+};
+pub use recovery::{
+    InMemoryRecoveryCodeStore, RecoveryCodeSet, RecoveryCodeStore, generate_recovery_codes,
+    hash_recovery_code,
+};
+pub use remote_introspection::RemoteIntrospection;
+#[cfg(feature = "saml")]
+pub use saml::{
+    AuthnRequest, IdpMetadata, InMemorySamlReplayStore, SamlBinding, SamlReplayStore,
+    SamlServiceProvider,
+};
+pub use secret::Secret;
+pub use throttle::{
+    InMemoryThrottleStore, Throttle, ThrottleAttempt, ThrottlePolicy, ThrottleState, ThrottleStore,
+};
+pub use totp::{InMemoryTotpStore, Totp, TotpAlgorithm, TotpEnrollment, TotpSecret, TotpStore};
+pub use webauthn::{
+    CredentialPublicKey, CredentialStore, InMemoryCredentialStore, RegistrationResponse, WebAuthn,
+    WebAuthnCredential,
+};
 
 pub use strategy::*;
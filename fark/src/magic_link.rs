@@ -0,0 +1,234 @@
+use crate::config::ConfigError;
+use crate::crypto::random_bytes;
+use crate::error::AuthError;
+use crate::secret::Secret;
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const MAGIC_LINK_TYP: &str = "magic_link";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers emails on behalf of fark. Implement this over SMTP or your provider's API.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: Email) -> BoxFuture<'_, Result<(), AuthError>>;
+}
+
+/// [`Mailer`] that keeps messages in memory, for tests and local development.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryMailer {
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+impl InMemoryMailer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent
+            .lock()
+            .map(|sent| sent.clone())
+            .unwrap_or_default()
+    }
+
+    /// Most recent message sent to `to`.
+    pub fn last_to(&self, to: &str) -> Option<Email> {
+        self.sent().into_iter().rev().find(|email| email.to == to)
+    }
+}
+
+impl Mailer for InMemoryMailer {
+    fn send(&self, email: Email) -> BoxFuture<'_, Result<(), AuthError>> {
+        let sent = self
+            .sent
+            .lock()
+            .map(|mut sent| sent.push(email))
+            .map_err(AuthError::from);
+        Box::pin(async move { sent })
+    }
+}
+
+/// Remembers used links by their id (`jti`) until they expire, so a link works once.
+pub trait MagicLinkStore: Send + Sync {
+    /// Marks `jti` used until `expires_at`, returning `false` if it already was.
+    /// Implementations must check and mark atomically so concurrent requests cannot
+    /// both use a link.
+    fn consume(&self, jti: String, expires_at: u64) -> BoxFuture<'_, Result<bool, AuthError>>;
+}
+
+/// Process-local [`MagicLinkStore`], for tests and single-instance deployments.
+/// Expired entries are pruned on every use.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryMagicLinkStore {
+    used: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl InMemoryMagicLinkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MagicLinkStore for InMemoryMagicLinkStore {
+    fn consume(&self, jti: String, expires_at: u64) -> BoxFuture<'_, Result<bool, AuthError>> {
+        let consumed = now().map_err(AuthError::from).and_then(|current| {
+            let mut used = self.used.lock()?;
+            used.retain(|_, expires_at| *expires_at >= current);
+            Ok(used.insert(jti, expires_at).is_none())
+        });
+        Box::pin(async move { consumed })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MagicLinkClaims {
+    sub: String,
+    exp: u64,
+    iat: u64,
+    jti: String,
+    typ: String,
+}
+
+/// Passwordless email login: signed, single-use, short-lived login links.
+///
+/// Cloning shares the store of used links, so keep a clone to send links after
+/// handing one to [`Fark::with_magic_link`](crate::Fark::with_magic_link).
+#[derive(Clone)]
+pub struct MagicLink {
+    secret: Secret,
+    base_url: String,
+    ttl_secs: u64,
+    subject: String,
+    mailer: Arc<dyn Mailer>,
+    store: Arc<dyn MagicLinkStore>,
+}
+
+impl MagicLink {
+    /// `base_url` is the page that receives the `token` query parameter and calls
+    /// `authenticate("magic_link", ..)` with it. `secret` signs the links and must
+    /// pass the same strength check as an `HS256` JWT secret. Used links are kept in
+    /// an [`InMemoryMagicLinkStore`]; see [`store`](Self::store) for more than one
+    /// instance.
+    pub fn new<M>(
+        secret: impl Into<Secret>,
+        base_url: impl Into<String>,
+        mailer: M,
+    ) -> Result<Self, ConfigError>
+    where
+        M: Mailer + 'static,
+    {
+        let secret = secret.into();
+        secret
+            .check_hmac_strength(Algorithm::HS256)
+            .map_err(|message| ConfigError::field("magic_link.secret", message))?;
+        Ok(Self {
+            secret,
+            base_url: base_url.into(),
+            ttl_secs: 900,
+            subject: "Your sign-in link".to_string(),
+            mailer: Arc::new(mailer),
+            store: Arc::new(InMemoryMagicLinkStore::new()),
+        })
+    }
+
+    /// Records used links in `store`, e.g. one shared by all instances.
+    pub fn store<S>(mut self, store: S) -> Self
+    where
+        S: MagicLinkStore + 'static,
+    {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Lifetime of a link. Defaults to 15 minutes.
+    pub fn ttl(mut self, secs: u64) -> Self {
+        self.ttl_secs = secs;
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.subject = subject.into();
+        self
+    }
+
+    /// Builds a login URL for `email` without sending it.
+    pub fn create_link(&self, email: &str) -> Result<String, AuthError> {
+        if self.secret.is_empty() {
+            return Err(AuthError::SecretNotFound);
+        }
+        let issued_at = now()?;
+        let claims = MagicLinkClaims {
+            sub: email.to_string(),
+            exp: issued_at
+                .checked_add(self.ttl_secs)
+                .ok_or_else(|| AuthError::internal("magic_link.ttl is out of range"))?,
+            iat: issued_at,
+            jti: URL_SAFE_NO_PAD.encode(random_bytes(16)),
+            typ: MAGIC_LINK_TYP.to_string(),
+        };
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(self.secret.expose().as_bytes()),
+        )
+        .map_err(AuthError::internal)?;
+
+        let separator = if self.base_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        Ok(format!("{}{separator}token={token}", self.base_url))
+    }
+
+    /// Creates a link for `email` and delivers it through the mailer.
+    pub async fn send(&self, email: &str) -> Result<(), AuthError> {
+        let link = self.create_link(email)?;
+        let minutes = self.ttl_secs.div_ceil(60);
+        self.mailer
+            .send(Email {
+                to: email.to_string(),
+                subject: self.subject.clone(),
+                body: format!(
+                    "Click the link below to sign in. It expires in {minutes} minutes and can only be used once.\n\n{link}\n"
+                ),
+            })
+            .await
+    }
+
+    /// Validates `token` and marks it as used, returning the email it was issued for.
+    pub async fn verify(&self, token: &str) -> Result<String, AuthError> {
+        if self.secret.is_empty() {
+            return Err(AuthError::SecretNotFound);
+        }
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        let claims = decode::<MagicLinkClaims>(
+            token,
+            &DecodingKey::from_secret(self.secret.expose().as_bytes()),
+            &validation,
+        )
+        .map_err(|_| AuthError::InvalidToken)?
+        .claims;
+        if claims.typ != MAGIC_LINK_TYP {
+            return Err(AuthError::InvalidToken);
+        }
+
+        if !self.store.consume(claims.jti, claims.exp).await? {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims.sub)
+    }
+}
@@ -0,0 +1,879 @@
+//! OAuth 2.0 authorization server (RFC 6749) issuing fark access tokens to
+//! third-party clients.
+//!
+//! Supported are the authorization code grant with PKCE (RFC 7636, `S256` only), the
+//! client credentials grant and the refresh token grant. Access tokens are JWTs
+//! signed like [`Fark::issue_jwt`] tokens, whose data carries the `client_id` and
+//! granted `scope`. They have their own `typ`, so first-party checks such as
+//! [`Fark::verify_jwt`] reject them; resource servers use
+//! [`Fark::verify_oauth_access_token`].
+
+use crate::crypto::{hex, random_bytes};
+use crate::error::AuthError;
+use crate::fark::Fark;
+use crate::http_auth::parse_basic;
+use crate::identity::Identity;
+use crate::input::AuthInput;
+use crate::introspection::TokenHintRequest;
+use crate::jwt::OAUTH_ACCESS_TYP;
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+/// Everything but the RFC 3986 unreserved characters.
+pub(crate) const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Errors of the authorization and token endpoints, with their RFC 6749 codes.
+#[derive(Debug, Error)]
+pub enum OAuthError {
+    #[error("invalid request: {0}")]
+    InvalidRequest(&'static str),
+    #[error("client authentication failed")]
+    InvalidClient,
+    #[error("authorization grant is invalid, expired or revoked")]
+    InvalidGrant,
+    #[error("client may not use this grant type")]
+    UnauthorizedClient,
+    #[error("unsupported grant type")]
+    UnsupportedGrantType,
+    #[error("unsupported response type")]
+    UnsupportedResponseType,
+    #[error("requested scope is invalid")]
+    InvalidScope,
+    #[error("resource owner denied the request")]
+    AccessDenied,
+    #[error(transparent)]
+    ServerError(#[from] AuthError),
+}
+
+impl OAuthError {
+    /// The `error` value sent to the client.
+    pub fn code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant => "invalid_grant",
+            OAuthError::UnauthorizedClient => "unauthorized_client",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::UnsupportedResponseType => "unsupported_response_type",
+            OAuthError::InvalidScope => "invalid_scope",
+            OAuthError::AccessDenied => "access_denied",
+            OAuthError::ServerError(_) => "server_error",
+        }
+    }
+
+    pub fn http_status(&self) -> u16 {
+        match self {
+            OAuthError::InvalidClient => 401,
+            OAuthError::ServerError(_) => 500,
+            _ => 400,
+        }
+    }
+
+    /// JSON error body of the token endpoint. Server errors carry no description.
+    pub fn body(&self) -> OAuthErrorBody {
+        OAuthErrorBody {
+            error: self.code(),
+            error_description: match self {
+                OAuthError::ServerError(_) => None,
+                other => Some(other.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OAuthErrorBody {
+    pub error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantType {
+    AuthorizationCode,
+    ClientCredentials,
+    RefreshToken,
+}
+
+/// A registered client. Confidential clients have a `secret_hash`; public clients
+/// (single-page and native apps) do not and can only use the authorization code grant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub name: String,
+    pub secret_hash: Option<String>,
+    /// Exact redirect URIs; no prefix or wildcard matching is done.
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<GrantType>,
+    /// Scopes the client may request.
+    pub scopes: Vec<String>,
+}
+
+impl OAuthClient {
+    /// A client allowed the authorization code and refresh token grants. The id and
+    /// secret are assigned by [`AuthorizationServer::register_client`].
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            client_id: String::new(),
+            name: name.into(),
+            secret_hash: None,
+            redirect_uris: Vec::new(),
+            grant_types: vec![GrantType::AuthorizationCode, GrantType::RefreshToken],
+            scopes: Vec::new(),
+        }
+    }
+
+    pub fn redirect_uri(mut self, uri: impl Into<String>) -> Self {
+        self.redirect_uris.push(uri.into());
+        self
+    }
+
+    pub fn grant_types<I>(mut self, grant_types: I) -> Self
+    where
+        I: IntoIterator<Item = GrantType>,
+    {
+        self.grant_types = grant_types.into_iter().collect();
+        self
+    }
+
+    pub fn scopes<I, S>(mut self, scopes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.secret_hash.is_none()
+    }
+
+    pub fn allows(&self, grant_type: GrantType) -> bool {
+        self.grant_types.contains(&grant_type)
+    }
+}
+
+/// A newly registered client. Hand `client_secret` to its owner once; only `client`
+/// is persisted.
+#[derive(Debug, Clone)]
+pub struct RegisteredClient {
+    pub client: OAuthClient,
+    pub client_secret: Option<String>,
+}
+
+/// Holds [`OAuthClient`]s by client id.
+pub trait ClientStore: Send + Sync {
+    fn insert(&self, client: OAuthClient) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    fn get(&self, client_id: &str) -> BoxFuture<'_, Result<Option<OAuthClient>, AuthError>>;
+}
+
+/// Process-local [`ClientStore`], for tests and single-instance deployments.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryClientStore {
+    clients: Arc<Mutex<HashMap<String, OAuthClient>>>,
+}
+
+impl InMemoryClientStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ClientStore for InMemoryClientStore {
+    fn insert(&self, client: OAuthClient) -> BoxFuture<'_, Result<(), AuthError>> {
+        let inserted = self
+            .clients
+            .lock()
+            .map(|mut clients| {
+                clients.insert(client.client_id.clone(), client);
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { inserted })
+    }
+
+    fn get(&self, client_id: &str) -> BoxFuture<'_, Result<Option<OAuthClient>, AuthError>> {
+        let client = self
+            .clients
+            .lock()
+            .map(|clients| clients.get(client_id).cloned())
+            .map_err(AuthError::from);
+        Box::pin(async move { client })
+    }
+}
+
+/// An issued authorization code, waiting to be exchanged at the token endpoint.
+#[derive(Debug, Clone)]
+pub struct AuthorizationCode {
+    pub client_id: String,
+    pub redirect_uri: String,
+    /// Whether the authorization request named `redirect_uri`, in which case the
+    /// token request must repeat it (RFC 6749 §4.1.3).
+    pub redirect_uri_supplied: bool,
+    pub code_challenge: String,
+    pub scopes: Vec<String>,
+    pub identity: Identity,
+    pub expires_at: u64,
+    /// OpenID Connect `nonce` of the authorization request, echoed in the ID token.
+    pub nonce: Option<String>,
+    /// When the user signed in, as a unix timestamp.
+    pub auth_time: u64,
+}
+
+/// What a refresh token stands for.
+#[derive(Debug, Clone)]
+pub struct RefreshGrant {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub identity: Identity,
+    pub expires_at: u64,
+    pub auth_time: u64,
+}
+
+/// Holds authorization codes and refresh tokens, keyed by their SHA-256. Both are
+/// single use, so `take_*` must remove the entry atomically.
+pub trait GrantStore: Send + Sync {
+    fn insert_code(
+        &self,
+        hash: String,
+        code: AuthorizationCode,
+    ) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    fn take_code(&self, hash: &str) -> BoxFuture<'_, Result<Option<AuthorizationCode>, AuthError>>;
+
+    fn insert_refresh_token(
+        &self,
+        hash: String,
+        grant: RefreshGrant,
+    ) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    fn take_refresh_token(
+        &self,
+        hash: &str,
+    ) -> BoxFuture<'_, Result<Option<RefreshGrant>, AuthError>>;
+}
+
+/// Process-local [`GrantStore`], for tests and single-instance deployments.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryGrantStore {
+    codes: Arc<Mutex<HashMap<String, AuthorizationCode>>>,
+    refresh_tokens: Arc<Mutex<HashMap<String, RefreshGrant>>>,
+}
+
+impl InMemoryGrantStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl GrantStore for InMemoryGrantStore {
+    fn insert_code(
+        &self,
+        hash: String,
+        code: AuthorizationCode,
+    ) -> BoxFuture<'_, Result<(), AuthError>> {
+        let inserted = self
+            .codes
+            .lock()
+            .map(|mut codes| {
+                codes.insert(hash, code);
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { inserted })
+    }
+
+    fn take_code(&self, hash: &str) -> BoxFuture<'_, Result<Option<AuthorizationCode>, AuthError>> {
+        let code = self
+            .codes
+            .lock()
+            .map(|mut codes| codes.remove(hash))
+            .map_err(AuthError::from);
+        Box::pin(async move { code })
+    }
+
+    fn insert_refresh_token(
+        &self,
+        hash: String,
+        grant: RefreshGrant,
+    ) -> BoxFuture<'_, Result<(), AuthError>> {
+        let inserted = self
+            .refresh_tokens
+            .lock()
+            .map(|mut refresh_tokens| {
+                refresh_tokens.insert(hash, grant);
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { inserted })
+    }
+
+    fn take_refresh_token(
+        &self,
+        hash: &str,
+    ) -> BoxFuture<'_, Result<Option<RefreshGrant>, AuthError>> {
+        let grant = self
+            .refresh_tokens
+            .lock()
+            .map(|mut refresh_tokens| refresh_tokens.remove(hash))
+            .map_err(AuthError::from);
+        Box::pin(async move { grant })
+    }
+}
+
+/// Query parameters of the authorization endpoint.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthorizationRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// Form parameters of the token endpoint. Client credentials may also come from an
+/// `Authorization: Basic` header, see [`TokenRequest::basic_credentials`].
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+impl TokenRequest {
+    /// Fills `client_id` and `client_secret` from a Basic `Authorization` header.
+    pub fn basic_credentials(mut self, header: &str) -> Result<Self, OAuthError> {
+        let (client_id, client_secret) = basic_client_credentials(header)?;
+        self.client_id = Some(client_id);
+        self.client_secret = Some(client_secret);
+        Ok(self)
+    }
+}
+
+pub(crate) fn basic_client_credentials(header: &str) -> Result<(String, String), OAuthError> {
+    match parse_basic(header) {
+        Ok(AuthInput::Local { mut data }) => Ok((
+            data.remove("username").unwrap_or_default(),
+            data.remove("password").unwrap_or_default(),
+        )),
+        _ => Err(OAuthError::InvalidClient),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    /// OpenID Connect ID token, issued when the `openid` scope was granted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+type ConsentHook = dyn Fn(Identity, OAuthClient, Vec<String>) -> BoxFuture<'static, Result<Vec<String>, AuthError>>
+    + Send
+    + Sync;
+
+/// The authorization server. Serve [`authorize`](Self::authorize) at the authorization
+/// endpoint once the user is signed in, and [`token`](Self::token) at the token
+/// endpoint. Cloning is cheap and clones share their stores.
+#[derive(Clone)]
+pub struct AuthorizationServer {
+    fark: Fark,
+    clients: Arc<dyn ClientStore>,
+    grants: Arc<dyn GrantStore>,
+    consent: Option<Arc<ConsentHook>>,
+    code_ttl: u64,
+    access_ttl: Option<u64>,
+    refresh_ttl: u64,
+}
+
+impl AuthorizationServer {
+    pub fn new<C, G>(fark: Fark, clients: C, grants: G) -> Self
+    where
+        C: ClientStore + 'static,
+        G: GrantStore + 'static,
+    {
+        Self {
+            fark,
+            clients: Arc::new(clients),
+            grants: Arc::new(grants),
+            consent: None,
+            code_ttl: 60,
+            access_ttl: None,
+            refresh_ttl: 30 * 86400,
+        }
+    }
+
+    /// Asks the user to approve a client. `f` gets the signed-in identity, the client
+    /// and the requested scopes, and returns the scopes the user granted. An error or
+    /// no scopes denies the request. Without a hook every valid request is approved.
+    pub fn consent<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Identity, OAuthClient, Vec<String>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<String>, AuthError>> + Send + 'static,
+    {
+        self.consent = Some(Arc::new(move |identity, client, scopes| {
+            Box::pin(f(identity, client, scopes))
+        }));
+        self
+    }
+
+    /// Lifetime of authorization codes. Defaults to 60 seconds.
+    pub fn code_ttl(mut self, secs: u64) -> Self {
+        self.code_ttl = secs;
+        self
+    }
+
+    /// Lifetime of access tokens. Defaults to [`Fark::jwt_ttl`].
+    pub fn access_ttl(mut self, secs: u64) -> Self {
+        self.access_ttl = Some(secs);
+        self
+    }
+
+    /// Lifetime of refresh tokens. Defaults to 30 days.
+    pub fn refresh_ttl(mut self, secs: u64) -> Self {
+        self.refresh_ttl = secs;
+        self
+    }
+
+    /// Assigns `client` an id and a secret, and stores it.
+    pub async fn register_client(
+        &self,
+        client: OAuthClient,
+    ) -> Result<RegisteredClient, AuthError> {
+        self.register(client, false).await
+    }
+
+    /// Assigns `client` an id, and no secret, and stores it. Public clients cannot
+    /// use the client credentials grant.
+    pub async fn register_public_client(
+        &self,
+        client: OAuthClient,
+    ) -> Result<RegisteredClient, AuthError> {
+        self.register(client, true).await
+    }
+
+    async fn register(
+        &self,
+        mut client: OAuthClient,
+        public: bool,
+    ) -> Result<RegisteredClient, AuthError> {
+        client.client_id = random_token(16);
+        let client_secret = (!public).then(|| random_token(32));
+        client.secret_hash = client_secret.as_deref().map(hash_token);
+        if public {
+            client
+                .grant_types
+                .retain(|grant| *grant != GrantType::ClientCredentials);
+        }
+        self.clients.insert(client.clone()).await?;
+        Ok(RegisteredClient {
+            client,
+            client_secret,
+        })
+    }
+
+    pub async fn client(&self, client_id: &str) -> Result<Option<OAuthClient>, AuthError> {
+        self.clients.get(client_id).await
+    }
+
+    /// Handles an authorization request on behalf of the signed-in `identity`.
+    ///
+    /// `Ok` is the URL to redirect the user agent to, carrying either the code or,
+    /// once the client and redirect URI are known to be valid, an error such as
+    /// `access_denied`. `Err` means the client or redirect URI could not be trusted;
+    /// show it to the user instead of redirecting.
+    pub async fn authorize(
+        &self,
+        request: &AuthorizationRequest,
+        identity: Identity,
+    ) -> Result<String, OAuthError> {
+        let client = self
+            .clients
+            .get(&request.client_id)
+            .await?
+            .ok_or(OAuthError::InvalidClient)?;
+        let redirect_uri = match &request.redirect_uri {
+            Some(uri) if client.redirect_uris.contains(uri) => uri.clone(),
+            None if client.redirect_uris.len() == 1 => client.redirect_uris[0].clone(),
+            _ => return Err(OAuthError::InvalidRequest("redirect_uri is not registered")),
+        };
+
+        let mut params = match self
+            .grant_code(request, &client, &redirect_uri, identity)
+            .await
+        {
+            Ok(code) => vec![("code", code)],
+            Err(err @ OAuthError::ServerError(_)) => return Err(err),
+            Err(err) => vec![("error", err.code().to_string())],
+        };
+        if let Some(state) = &request.state {
+            params.push(("state", state.clone()));
+        }
+        let query: Vec<String> = params
+            .iter()
+            .map(|(key, value)| format!("{key}={}", utf8_percent_encode(value, QUERY_VALUE)))
+            .collect();
+        let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+        Ok(format!("{redirect_uri}{separator}{}", query.join("&")))
+    }
+
+    async fn grant_code(
+        &self,
+        request: &AuthorizationRequest,
+        client: &OAuthClient,
+        redirect_uri: &str,
+        identity: Identity,
+    ) -> Result<String, OAuthError> {
+        if request.response_type != "code" {
+            return Err(OAuthError::UnsupportedResponseType);
+        }
+        if !client.allows(GrantType::AuthorizationCode) {
+            return Err(OAuthError::UnauthorizedClient);
+        }
+        let code_challenge = match (
+            &request.code_challenge,
+            request.code_challenge_method.as_deref(),
+        ) {
+            (Some(challenge), Some("S256")) => challenge.clone(),
+            _ => return Err(OAuthError::InvalidRequest("PKCE with S256 is required")),
+        };
+        let requested = requested_scopes(request.scope.as_deref(), client)?;
+
+        let scopes = match &self.consent {
+            Some(consent) => consent(identity.clone(), client.clone(), requested.clone())
+                .await
+                .map_err(|_| OAuthError::AccessDenied)?,
+            None => requested.clone(),
+        };
+        if scopes.is_empty() || scopes.iter().any(|scope| !requested.contains(scope)) {
+            return Err(OAuthError::AccessDenied);
+        }
+
+        let current = now().map_err(AuthError::from)?;
+        let auth_time = identity
+            .auth_time
+            .or_else(|| identity.data.get("auth_time").and_then(Value::as_u64))
+            .unwrap_or(current);
+        let code = random_token(32);
+        let grant = AuthorizationCode {
+            client_id: client.client_id.clone(),
+            redirect_uri: redirect_uri.to_string(),
+            redirect_uri_supplied: request.redirect_uri.is_some(),
+            code_challenge,
+            scopes,
+            identity,
+            expires_at: current
+                .checked_add(self.code_ttl)
+                .ok_or_else(|| AuthError::internal("oauth code_ttl is out of range"))?,
+            nonce: request.nonce.clone(),
+            auth_time,
+        };
+        self.grants.insert_code(hash_token(&code), grant).await?;
+        Ok(code)
+    }
+
+    /// Handles a token request for any of the supported grants.
+    pub async fn token(&self, request: TokenRequest) -> Result<TokenResponse, OAuthError> {
+        let grant_type = match request.grant_type.as_str() {
+            "authorization_code" => GrantType::AuthorizationCode,
+            "client_credentials" => GrantType::ClientCredentials,
+            "refresh_token" => GrantType::RefreshToken,
+            _ => return Err(OAuthError::UnsupportedGrantType),
+        };
+        let client = self
+            .authenticate_client(
+                request.client_id.as_deref(),
+                request.client_secret.as_deref(),
+            )
+            .await?;
+        if !client.allows(grant_type) {
+            return Err(OAuthError::UnauthorizedClient);
+        }
+
+        match grant_type {
+            GrantType::AuthorizationCode => self.exchange_code(&client, request).await,
+            GrantType::ClientCredentials => {
+                if client.is_public() {
+                    return Err(OAuthError::UnauthorizedClient);
+                }
+                let scopes = requested_scopes(request.scope.as_deref(), &client)?;
+                let identity = Identity::new(client.client_id.clone(), Value::Null);
+                self.issue(&client, identity, scopes, false, None, None)
+                    .await
+            }
+            GrantType::RefreshToken => self.refresh(&client, request).await,
+        }
+    }
+
+    async fn authenticate_client(
+        &self,
+        client_id: Option<&str>,
+        client_secret: Option<&str>,
+    ) -> Result<OAuthClient, OAuthError> {
+        let client = self
+            .clients
+            .get(client_id.ok_or(OAuthError::InvalidClient)?)
+            .await?
+            .ok_or(OAuthError::InvalidClient)?;
+        if let Some(secret_hash) = &client.secret_hash {
+            let secret = client_secret.ok_or(OAuthError::InvalidClient)?;
+            if !bool::from(hash_token(secret).as_bytes().ct_eq(secret_hash.as_bytes())) {
+                return Err(OAuthError::InvalidClient);
+            }
+        }
+        Ok(client)
+    }
+
+    /// RFC 7009 revocation of a refresh token or access token issued to the
+    /// requesting client. Unknown tokens and tokens of other clients are ignored, as
+    /// the RFC asks. Access tokens need [`Fark::with_revocation_store`].
+    pub async fn revoke(&self, request: &TokenHintRequest) -> Result<(), OAuthError> {
+        let client = self
+            .authenticate_client(
+                request.client_id.as_deref(),
+                request.client_secret.as_deref(),
+            )
+            .await?;
+
+        let hash = hash_token(&request.token);
+        if let Some(grant) = self.grants.take_refresh_token(&hash).await? {
+            if grant.client_id != client.client_id {
+                self.grants.insert_refresh_token(hash, grant).await?;
+            }
+            return Ok(());
+        }
+
+        let issued_to = self
+            .fark
+            .access_token_claims(&request.token)
+            .ok()
+            .filter(|claims| claims.typ.as_deref() == Some(OAUTH_ACCESS_TYP))
+            .and_then(|claims| claims.extra.get("client_id").cloned());
+        if issued_to.as_ref().and_then(Value::as_str) == Some(client.client_id.as_str()) {
+            self.fark.revoke_jwt(&request.token).await?;
+        }
+        Ok(())
+    }
+
+    async fn exchange_code(
+        &self,
+        client: &OAuthClient,
+        request: TokenRequest,
+    ) -> Result<TokenResponse, OAuthError> {
+        let code = request
+            .code
+            .as_deref()
+            .ok_or(OAuthError::InvalidRequest("code is required"))?;
+        let verifier = request
+            .code_verifier
+            .as_deref()
+            .ok_or(OAuthError::InvalidRequest("code_verifier is required"))?;
+        let grant = self
+            .grants
+            .take_code(&hash_token(code))
+            .await?
+            .ok_or(OAuthError::InvalidGrant)?;
+
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let redirect_uri_mismatch = match request.redirect_uri.as_deref() {
+            Some(uri) => uri != grant.redirect_uri,
+            None => grant.redirect_uri_supplied,
+        };
+        if grant.client_id != client.client_id
+            || grant.expires_at <= now().map_err(AuthError::from)?
+            || redirect_uri_mismatch
+            || !(43..=128).contains(&verifier.len())
+            || !bool::from(challenge.as_bytes().ct_eq(grant.code_challenge.as_bytes()))
+        {
+            return Err(OAuthError::InvalidGrant);
+        }
+
+        let refresh = client.allows(GrantType::RefreshToken);
+        self.issue(
+            client,
+            grant.identity,
+            grant.scopes,
+            refresh,
+            Some(grant.auth_time),
+            grant.nonce.as_deref(),
+        )
+        .await
+    }
+
+    /// Exchanges a refresh token, rotating it. The scope may be narrowed, not widened.
+    async fn refresh(
+        &self,
+        client: &OAuthClient,
+        request: TokenRequest,
+    ) -> Result<TokenResponse, OAuthError> {
+        let token = request
+            .refresh_token
+            .as_deref()
+            .ok_or(OAuthError::InvalidRequest("refresh_token is required"))?;
+        let hash = hash_token(token);
+        let grant = self
+            .grants
+            .take_refresh_token(&hash)
+            .await?
+            .ok_or(OAuthError::InvalidGrant)?;
+        // Another client's token goes back untouched, so it cannot be used to revoke it.
+        if grant.client_id != client.client_id {
+            self.grants.insert_refresh_token(hash, grant).await?;
+            return Err(OAuthError::InvalidGrant);
+        }
+        if grant.expires_at <= now().map_err(AuthError::from)? {
+            return Err(OAuthError::InvalidGrant);
+        }
+
+        let scopes = match request.scope.as_deref() {
+            Some(scope) => {
+                let scopes = split_scope(scope);
+                if scopes.is_empty() || scopes.iter().any(|scope| !grant.scopes.contains(scope)) {
+                    return Err(OAuthError::InvalidScope);
+                }
+                scopes
+            }
+            None => grant.scopes,
+        };
+        self.issue(
+            client,
+            grant.identity,
+            scopes,
+            true,
+            Some(grant.auth_time),
+            None,
+        )
+        .await
+    }
+
+    /// Issues the token response. `auth_time` is set for grants made by a user, and
+    /// an ID token is added when they granted the `openid` scope.
+    async fn issue(
+        &self,
+        client: &OAuthClient,
+        identity: Identity,
+        scopes: Vec<String>,
+        refresh: bool,
+        auth_time: Option<u64>,
+        nonce: Option<&str>,
+    ) -> Result<TokenResponse, OAuthError> {
+        let scope = scopes.join(" ");
+        let expires_in = self.access_ttl.unwrap_or_else(|| self.fark.jwt_ttl());
+        let access = Identity {
+            data: with_grant(identity.data.clone(), &client.client_id, &scope),
+            ..identity.clone()
+        };
+        let access_token = self.fark.issue_oauth_access_token(access, expires_in)?;
+        let id_token = match auth_time {
+            Some(auth_time) if scopes.iter().any(|scope| scope == "openid") => {
+                Some(self.fark.issue_id_token(
+                    &identity,
+                    &client.client_id,
+                    auth_time,
+                    nonce,
+                    expires_in,
+                )?)
+            }
+            _ => None,
+        };
+
+        let refresh_token = if refresh {
+            let token = random_token(32);
+            let grant = RefreshGrant {
+                client_id: client.client_id.clone(),
+                scopes,
+                identity,
+                expires_at: now()
+                    .map_err(AuthError::from)?
+                    .checked_add(self.refresh_ttl)
+                    .ok_or_else(|| AuthError::internal("oauth refresh_ttl is out of range"))?,
+                auth_time: auth_time.unwrap_or_default(),
+            };
+            self.grants
+                .insert_refresh_token(hash_token(&token), grant)
+                .await?;
+            Some(token)
+        } else {
+            None
+        };
+
+        Ok(TokenResponse {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in,
+            refresh_token,
+            scope,
+            id_token,
+        })
+    }
+}
+
+/// Scopes of a request, defaulting to everything the client may request.
+fn requested_scopes(scope: Option<&str>, client: &OAuthClient) -> Result<Vec<String>, OAuthError> {
+    let Some(scope) = scope else {
+        return Ok(client.scopes.clone());
+    };
+    let scopes = split_scope(scope);
+    if scopes.iter().any(|scope| !client.scopes.contains(scope)) {
+        return Err(OAuthError::InvalidScope);
+    }
+    Ok(scopes)
+}
+
+fn split_scope(scope: &str) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in scope.split_whitespace() {
+        if !scopes.iter().any(|s| s == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    scopes
+}
+
+/// Adds `client_id` and `scope` to the identity data of an access token.
+fn with_grant(data: Value, client_id: &str, scope: &str) -> Value {
+    let mut map = match data {
+        Value::Object(map) => map,
+        Value::Null => Map::new(),
+        other => Map::from_iter([("data".to_string(), other)]),
+    };
+    map.insert("client_id".to_string(), Value::from(client_id));
+    map.insert("scope".to_string(), Value::from(scope));
+    Value::Object(map)
+}
+
+fn random_token(len: usize) -> String {
+    URL_SAFE_NO_PAD.encode(random_bytes(len))
+}
+
+/// Codes, refresh tokens and client secrets are random, so a fast hash is enough.
+fn hash_token(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,224 @@
+//! OpenID Connect provider on top of the [`AuthorizationServer`](crate::AuthorizationServer):
+//! ID tokens, the discovery document, the JWK set and userinfo.
+//!
+//! Relying parties verify ID tokens with the published keys, so configure an RSA or
+//! ECDSA signing key; with the `HS*` algorithms the JWK set stays empty.
+
+use crate::error::AuthError;
+use crate::fark::{Fark, read};
+use crate::identity::Identity;
+use crate::time::now;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Identity data fields released by each standard scope.
+const SCOPE_CLAIMS: &[(&str, &[&str])] = &[
+    (
+        "profile",
+        &[
+            "name",
+            "family_name",
+            "given_name",
+            "middle_name",
+            "nickname",
+            "preferred_username",
+            "profile",
+            "picture",
+            "website",
+            "gender",
+            "birthdate",
+            "zoneinfo",
+            "locale",
+            "updated_at",
+        ],
+    ),
+    ("email", &["email", "email_verified"]),
+    ("address", &["address"]),
+    ("phone", &["phone_number", "phone_number_verified"]),
+];
+
+// No `extra` member, so an ID token never decodes as an access token.
+#[derive(Debug, Serialize, Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    aud: String,
+    exp: u64,
+    iat: u64,
+    auth_time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    acr: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    amr: Vec<String>,
+    azp: String,
+}
+
+/// OpenID Provider metadata, served at `/.well-known/openid-configuration`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveryDocument {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub revocation_endpoint: String,
+    pub introspection_endpoint: String,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub scopes_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+}
+
+impl Fark {
+    /// Signs an ID token for `identity`, issued to `client_id`. The `acr` claim is
+    /// taken from the identity data, `amr` from the identity.
+    pub(crate) fn issue_id_token(
+        &self,
+        identity: &Identity,
+        client_id: &str,
+        auth_time: u64,
+        nonce: Option<&str>,
+        ttl_secs: u64,
+    ) -> Result<String, AuthError> {
+        let issued_at = now()?;
+        let claims = IdTokenClaims {
+            iss: self.oidc_issuer()?,
+            sub: identity.user_id.clone(),
+            aud: client_id.to_string(),
+            exp: issued_at
+                .checked_add(ttl_secs)
+                .ok_or_else(|| AuthError::internal("id token ttl_secs is out of range"))?,
+            iat: issued_at,
+            auth_time,
+            nonce: nonce.map(str::to_string),
+            acr: identity
+                .data
+                .get("acr")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            amr: identity.amr.clone(),
+            azp: client_id.to_string(),
+        };
+        self.encode_token(&claims)
+    }
+
+    /// Metadata for the discovery endpoint, with the endpoints at their conventional
+    /// paths under the issuer (`/authorize`, `/token`, `/userinfo`, `/jwks`,
+    /// `/revoke`, `/introspect`). Change the fields if yours differ. Fails unless
+    /// `jwt.issuer` is configured.
+    pub fn oidc_discovery(&self) -> Result<DiscoveryDocument, AuthError> {
+        let issuer = self.oidc_issuer()?;
+        let endpoint = |path: &str| format!("{}/{path}", issuer.trim_end_matches('/'));
+        let algorithm = format!("{:?}", read(&self.jwt).algorithm);
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+
+        let mut scopes = vec!["openid"];
+        let mut claims = vec![
+            "sub",
+            "iss",
+            "aud",
+            "exp",
+            "iat",
+            "auth_time",
+            "nonce",
+            "acr",
+            "amr",
+            "azp",
+        ];
+        for (scope, scope_claims) in SCOPE_CLAIMS {
+            scopes.push(scope);
+            claims.extend_from_slice(scope_claims);
+        }
+
+        Ok(DiscoveryDocument {
+            authorization_endpoint: endpoint("authorize"),
+            token_endpoint: endpoint("token"),
+            userinfo_endpoint: endpoint("userinfo"),
+            jwks_uri: endpoint("jwks"),
+            revocation_endpoint: endpoint("revoke"),
+            introspection_endpoint: endpoint("introspect"),
+            issuer,
+            response_types_supported: strings(&["code"]),
+            grant_types_supported: strings(&[
+                "authorization_code",
+                "client_credentials",
+                "refresh_token",
+            ]),
+            subject_types_supported: strings(&["public"]),
+            id_token_signing_alg_values_supported: vec![algorithm],
+            scopes_supported: strings(&scopes),
+            claims_supported: strings(&claims),
+            code_challenge_methods_supported: strings(&["S256"]),
+            token_endpoint_auth_methods_supported: strings(&[
+                "client_secret_basic",
+                "client_secret_post",
+                "none",
+            ]),
+        })
+    }
+
+    /// Public keys for verifying fark's tokens. Empty for the `HS*` algorithms,
+    /// whose key is secret, and for EdDSA. RSA keys must be PKCS#1 PEM.
+    pub fn jwks(&self) -> Result<JwkSet, AuthError> {
+        let jwt = read(&self.jwt);
+        let keys = match jwt.algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 | Algorithm::EdDSA => Vec::new(),
+            algorithm => {
+                vec![
+                    Jwk::from_encoding_key(jwt.encoding_key()?, algorithm)
+                        .map_err(AuthError::internal)?,
+                ]
+            }
+        };
+        Ok(JwkSet { keys })
+    }
+
+    /// Userinfo for an OAuth access token granted the `openid` scope, with the
+    /// claims of its other scopes. Revoked tokens are rejected.
+    pub async fn userinfo(&self, access_token: String) -> Result<Value, AuthError> {
+        let identity = self
+            .verify_oauth_access_token(&access_token, &["openid"])
+            .await?;
+        userinfo_claims(&identity)
+    }
+
+    fn oidc_issuer(&self) -> Result<String, AuthError> {
+        read(&self.jwt)
+            .issuer
+            .clone()
+            .ok_or_else(|| AuthError::internal("jwt.issuer must be set for OpenID Connect"))
+    }
+}
+
+/// Userinfo claims of an identity from an access token issued by the
+/// [`AuthorizationServer`](crate::AuthorizationServer): `sub`, plus the identity
+/// data fields the granted scopes release (e.g. `email` and `email_verified` for
+/// `email`). Fails with [`AuthError::InvalidToken`] without the `openid` scope.
+pub fn userinfo_claims(identity: &Identity) -> Result<Value, AuthError> {
+    let scopes = identity.scopes();
+    if !scopes.contains(&"openid") {
+        return Err(AuthError::InvalidToken);
+    }
+
+    let mut claims = Map::new();
+    claims.insert("sub".to_string(), Value::from(identity.user_id.as_str()));
+    for (scope, scope_claims) in SCOPE_CLAIMS {
+        if !scopes.contains(scope) {
+            continue;
+        }
+        for claim in *scope_claims {
+            if let Some(value) = identity.data.get(*claim) {
+                claims.insert(claim.to_string(), value.clone());
+            }
+        }
+    }
+    Ok(Value::Object(claims))
+}
@@ -0,0 +1,322 @@
+use crate::config::ConfigError;
+use crate::crypto::hex;
+use crate::error::AuthError;
+use crate::secret::Secret;
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::Algorithm;
+use rand::Rng;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+
+/// Where a one-time code is delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpChannel {
+    Sms,
+    Email,
+}
+
+/// A code ready to be delivered. `code` is the only plaintext copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpMessage {
+    pub channel: OtpChannel,
+    pub destination: String,
+    pub code: String,
+    pub expires_in: u64,
+}
+
+/// Delivers one-time codes over SMS, email or any other transport.
+pub trait CodeSender: Send + Sync {
+    fn send(&self, message: OtpMessage) -> BoxFuture<'_, Result<(), AuthError>>;
+}
+
+/// [`CodeSender`] that records every message instead of delivering it, for tests.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingCodeSender {
+    sent: Arc<Mutex<Vec<OtpMessage>>>,
+}
+
+impl RecordingCodeSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent(&self) -> Vec<OtpMessage> {
+        self.sent
+            .lock()
+            .map(|sent| sent.clone())
+            .unwrap_or_default()
+    }
+
+    /// Most recent code sent to `destination`.
+    pub fn last_code(&self, destination: &str) -> Option<String> {
+        self.sent()
+            .into_iter()
+            .rev()
+            .find(|message| message.destination == destination)
+            .map(|message| message.code)
+    }
+}
+
+impl CodeSender for RecordingCodeSender {
+    fn send(&self, message: OtpMessage) -> BoxFuture<'_, Result<(), AuthError>> {
+        let sent = self
+            .sent
+            .lock()
+            .map(|mut sent| sent.push(message))
+            .map_err(AuthError::from);
+        Box::pin(async move { sent })
+    }
+}
+
+/// A pending code as kept by an [`OtpStore`]: only its keyed hash is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredOtp {
+    pub code_hash: String,
+    pub expires_at: u64,
+    pub attempts: u32,
+}
+
+/// Holds pending codes per account. Issuing a new code replaces the previous one.
+pub trait OtpStore: Send + Sync {
+    /// Stores `otp`, replacing the pending code. The attempt count of a replaced code
+    /// that has not expired carries over, so requesting a new code does not buy fresh
+    /// guesses; implementations must replace and carry over atomically.
+    fn put(&self, account: &str, otp: StoredOtp) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    fn get(&self, account: &str) -> BoxFuture<'_, Result<Option<StoredOtp>, AuthError>>;
+
+    /// Increments the attempt counter and returns the new value, or zero without a
+    /// pending code. Called before every comparison; implementations must increment
+    /// atomically so concurrent guesses cannot exceed the limit.
+    fn record_attempt(&self, account: &str) -> BoxFuture<'_, Result<u32, AuthError>>;
+
+    /// Removes the pending code if its hash is `code_hash`, returning whether it did.
+    /// Implementations must check and remove atomically so a code works only once.
+    fn consume(&self, account: &str, code_hash: &str) -> BoxFuture<'_, Result<bool, AuthError>>;
+
+    fn remove(&self, account: &str) -> BoxFuture<'_, Result<(), AuthError>>;
+}
+
+/// Process-local [`OtpStore`], for tests and single-instance deployments.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryOtpStore {
+    codes: Arc<Mutex<HashMap<String, StoredOtp>>>,
+}
+
+impl InMemoryOtpStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OtpStore for InMemoryOtpStore {
+    fn put(&self, account: &str, mut otp: StoredOtp) -> BoxFuture<'_, Result<(), AuthError>> {
+        let stored = now().map_err(AuthError::from).and_then(|current| {
+            let mut codes = self.codes.lock()?;
+            if let Some(previous) = codes.get(account)
+                && previous.expires_at >= current
+            {
+                otp.attempts = otp.attempts.max(previous.attempts);
+            }
+            codes.insert(account.to_string(), otp);
+            Ok(())
+        });
+        Box::pin(async move { stored })
+    }
+
+    fn get(&self, account: &str) -> BoxFuture<'_, Result<Option<StoredOtp>, AuthError>> {
+        let found = self
+            .codes
+            .lock()
+            .map(|codes| codes.get(account).cloned())
+            .map_err(AuthError::from);
+        Box::pin(async move { found })
+    }
+
+    fn record_attempt(&self, account: &str) -> BoxFuture<'_, Result<u32, AuthError>> {
+        let attempts = self
+            .codes
+            .lock()
+            .map(|mut codes| {
+                codes.get_mut(account).map_or(0, |otp| {
+                    otp.attempts += 1;
+                    otp.attempts
+                })
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { attempts })
+    }
+
+    fn consume(&self, account: &str, code_hash: &str) -> BoxFuture<'_, Result<bool, AuthError>> {
+        let consumed = self
+            .codes
+            .lock()
+            .map(|mut codes| {
+                let matches = codes.get(account).is_some_and(|otp| {
+                    bool::from(otp.code_hash.as_bytes().ct_eq(code_hash.as_bytes()))
+                });
+                if matches {
+                    codes.remove(account);
+                }
+                matches
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { consumed })
+    }
+
+    fn remove(&self, account: &str) -> BoxFuture<'_, Result<(), AuthError>> {
+        let removed = self
+            .codes
+            .lock()
+            .map(|mut codes| {
+                codes.remove(account);
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { removed })
+    }
+}
+
+/// Numeric one-time codes delivered by SMS or email.
+///
+/// Cloning shares the store and sender, so keep a clone to issue codes after
+/// handing one to [`Fark::with_otp`](crate::Fark::with_otp).
+#[derive(Clone)]
+pub struct OneTimeCode {
+    secret: Secret,
+    digits: u32,
+    ttl_secs: u64,
+    max_attempts: u32,
+    store: Arc<dyn OtpStore>,
+    sender: Arc<dyn CodeSender>,
+}
+
+impl OneTimeCode {
+    /// Defaults: 6 digits, valid for 5 minutes, 5 wrong guesses before the code is burned.
+    ///
+    /// Codes are stored as an HMAC keyed with `secret`, which must pass the same
+    /// strength check as an `HS256` JWT secret: a short code space is only hard to
+    /// reverse from the store if the key is.
+    pub fn new<S, C>(secret: impl Into<Secret>, store: S, sender: C) -> Result<Self, ConfigError>
+    where
+        S: OtpStore + 'static,
+        C: CodeSender + 'static,
+    {
+        let secret = secret.into();
+        secret
+            .check_hmac_strength(Algorithm::HS256)
+            .map_err(|message| ConfigError::field("otp.secret", message))?;
+        Ok(Self {
+            secret,
+            digits: 6,
+            ttl_secs: 300,
+            max_attempts: 5,
+            store: Arc::new(store),
+            sender: Arc::new(sender),
+        })
+    }
+
+    /// Code length, 4 to 10 digits.
+    pub fn digits(mut self, digits: u32) -> Result<Self, ConfigError> {
+        if !(4..=10).contains(&digits) {
+            return Err(ConfigError::field("otp.digits", "must be between 4 and 10"));
+        }
+        self.digits = digits;
+        Ok(self)
+    }
+
+    pub fn ttl(mut self, secs: u64) -> Self {
+        self.ttl_secs = secs;
+        self
+    }
+
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Generates a code for `account`, stores its hash and sends it to `destination`.
+    /// Wrong guesses at the code it replaces still count against the new one until
+    /// the old one would have expired.
+    pub async fn issue(
+        &self,
+        account: &str,
+        channel: OtpChannel,
+        destination: &str,
+    ) -> Result<(), AuthError> {
+        let code = format!(
+            "{:0width$}",
+            rand::rng().random_range(0..10u64.pow(self.digits)),
+            width = self.digits as usize
+        );
+        let expires_at = now()?
+            .checked_add(self.ttl_secs)
+            .ok_or_else(|| AuthError::internal("otp.ttl is out of range"))?;
+
+        self.store
+            .put(
+                account,
+                StoredOtp {
+                    code_hash: self.hash_code(account, &code),
+                    expires_at,
+                    attempts: 0,
+                },
+            )
+            .await?;
+        self.sender
+            .send(OtpMessage {
+                channel,
+                destination: destination.to_string(),
+                code,
+                expires_in: self.ttl_secs,
+            })
+            .await
+    }
+
+    /// Checks `code` for `account`. A correct code is consumed; an expired code is
+    /// discarded. One that has seen too many wrong guesses stays burned until it
+    /// expires, so that a new code cannot reset the count.
+    ///
+    /// The attempt is counted before the code is compared, so concurrent guesses
+    /// cannot get past `max_attempts`.
+    pub async fn verify(&self, account: &str, code: &str) -> Result<(), AuthError> {
+        let stored = self
+            .store
+            .get(account)
+            .await?
+            .ok_or(AuthError::CodeMisMatch)?;
+        if stored.expires_at < now()? {
+            self.store.remove(account).await?;
+            return Err(AuthError::CodeMisMatch);
+        }
+
+        let attempts = self.store.record_attempt(account).await?;
+        if attempts == 0 {
+            return Err(AuthError::CodeMisMatch);
+        }
+        if attempts <= self.max_attempts
+            && self
+                .store
+                .consume(account, &self.hash_code(account, code))
+                .await?
+        {
+            return Ok(());
+        }
+        Err(AuthError::CodeMisMatch)
+    }
+
+    /// Codes are short, so a plain hash of one is reversed by trying them all. The
+    /// server key prevents that, and the account keeps equal codes from producing
+    /// equal hashes across accounts.
+    fn hash_code(&self, account: &str, code: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.expose().as_bytes())
+            .expect("hmac accepts any key");
+        mac.update(account.as_bytes());
+        mac.update(&[0]);
+        mac.update(code.trim().as_bytes());
+        hex(&mac.finalize().into_bytes())
+    }
+}
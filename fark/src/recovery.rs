@@ -0,0 +1,84 @@
+use crate::crypto::{base32_encode, hex, random_bytes};
+use crate::error::AuthError;
+use crate::strategy::BoxFuture;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// A freshly generated set of recovery codes. Show `codes` to the user once and
+/// persist only `hashes`.
+#[derive(Debug, Clone)]
+pub struct RecoveryCodeSet {
+    pub codes: Vec<String>,
+    pub hashes: Vec<String>,
+}
+
+/// Generates `count` single-use codes of the form `xxxxx-xxxxx` (50 bits each).
+pub fn generate_recovery_codes(count: usize) -> RecoveryCodeSet {
+    let codes: Vec<String> = (0..count)
+        .map(|_| {
+            let raw = base32_encode(&random_bytes(7)).to_ascii_lowercase();
+            format!("{}-{}", &raw[..5], &raw[5..10])
+        })
+        .collect();
+    let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    RecoveryCodeSet { codes, hashes }
+}
+
+/// Hashes a recovery code for storage or lookup. Case and dashes are ignored so
+/// users can type codes loosely.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex(&Sha256::digest(normalized.as_bytes()))
+}
+
+/// Holds the hashed recovery codes of each account.
+pub trait RecoveryCodeStore: Send + Sync {
+    /// Removes `code_hash` from `account`'s set, returning whether it was present.
+    /// Implementations must check and remove atomically so a code works only once.
+    fn consume(&self, account: &str, code_hash: &str) -> BoxFuture<'_, Result<bool, AuthError>>;
+}
+
+/// Process-local [`RecoveryCodeStore`], for tests and single-instance deployments.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRecoveryCodeStore {
+    hashes: Arc<Mutex<HashMap<String, HashSet<String>>>>,
+}
+
+impl InMemoryRecoveryCodeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces `account`'s codes with `hashes`, e.g. after regenerating the set.
+    pub fn set(&self, account: impl Into<String>, hashes: Vec<String>) {
+        if let Ok(mut all) = self.hashes.lock() {
+            all.insert(account.into(), hashes.into_iter().collect());
+        }
+    }
+
+    pub fn remaining(&self, account: &str) -> usize {
+        self.hashes
+            .lock()
+            .map(|all| all.get(account).map_or(0, HashSet::len))
+            .unwrap_or(0)
+    }
+}
+
+impl RecoveryCodeStore for InMemoryRecoveryCodeStore {
+    fn consume(&self, account: &str, code_hash: &str) -> BoxFuture<'_, Result<bool, AuthError>> {
+        let consumed = self
+            .hashes
+            .lock()
+            .map(|mut all| {
+                all.get_mut(account)
+                    .is_some_and(|hashes| hashes.remove(code_hash))
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { consumed })
+    }
+}
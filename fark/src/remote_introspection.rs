@@ -0,0 +1,186 @@
+//! Validation of opaque bearer tokens from another issuer through its RFC 7662
+//! introspection endpoint.
+
+use crate::crypto::hex;
+use crate::error::AuthError;
+use crate::identity::Identity;
+use crate::secret::Secret;
+use crate::time::now;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Active responses by token hash, with their `exp`.
+type Cache = HashMap<String, (Map<String, Value>, u64)>;
+
+/// Client of a remote introspection endpoint.
+///
+/// Active responses are cached until their `exp`, so a token is introspected once
+/// rather than on every request; responses without `exp` are not cached. Inactive
+/// responses are never cached, so a token becomes usable as soon as the issuer
+/// reports it active. Revocations at the issuer are noticed only once the cached
+/// entry expires.
+///
+/// With [`issuer`](Self::issuer) or [`audience`](Self::audience) set, active
+/// responses whose `iss` or `aud` do not match are rejected, as are responses whose
+/// `nbf` is still in the future.
+#[derive(Clone)]
+pub struct RemoteIntrospection {
+    endpoint: String,
+    client: reqwest::Client,
+    credentials: Option<(String, Secret)>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl RemoteIntrospection {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            client: reqwest::Client::new(),
+            credentials: None,
+            issuer: None,
+            audience: None,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Authenticates to the endpoint with HTTP Basic, as most issuers require.
+    pub fn client_credentials(
+        mut self,
+        client_id: impl Into<String>,
+        client_secret: impl Into<Secret>,
+    ) -> Self {
+        self.credentials = Some((client_id.into(), client_secret.into()));
+        self
+    }
+
+    /// Rejects tokens whose `iss` is not `issuer`.
+    pub fn issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Rejects tokens whose `aud` (a string or an array) does not contain `audience`.
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    /// Uses `client` for requests, e.g. one with timeouts or a proxy configured.
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// The introspection response of an active token. Inactive tokens fail with
+    /// [`AuthError::InvalidToken`]; an unreachable endpoint or an unexpected response
+    /// with [`AuthError::InternalError`]. Responses failing the configured `iss` or
+    /// `aud`, or not yet valid by `nbf`, fail with [`AuthError::InvalidToken`].
+    pub async fn introspect(&self, token: &str) -> Result<Map<String, Value>, AuthError> {
+        let key = hex(&Sha256::digest(token.as_bytes()));
+        let current = now()?;
+        if let Some(response) = self.cached(&key, current) {
+            return Ok(response);
+        }
+
+        let mut request = self
+            .client
+            .post(&self.endpoint)
+            .header("Accept", "application/json")
+            .form(&[("token", token), ("token_type_hint", "access_token")]);
+        if let Some((client_id, client_secret)) = &self.credentials {
+            request = request.basic_auth(client_id, Some(client_secret.expose()));
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(AuthError::internal)?;
+        let body = response.bytes().await.map_err(AuthError::internal)?;
+        let response: Map<String, Value> =
+            serde_json::from_slice(&body).map_err(AuthError::internal)?;
+
+        if response.get("active") != Some(&Value::Bool(true)) {
+            return Err(AuthError::InvalidToken);
+        }
+        self.check_claims(&response, current)?;
+        match response.get("exp").and_then(Value::as_u64) {
+            Some(exp) if exp <= current => return Err(AuthError::InvalidToken),
+            Some(exp) => {
+                let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+                cache.retain(|_, (_, expires_at)| *expires_at > current);
+                cache.insert(key, (response.clone(), exp));
+            }
+            None => {}
+        }
+        Ok(response)
+    }
+
+    /// Introspects `token` and maps the response into an [`Identity`]: `sub` (or
+    /// `username`, or `client_id` for tokens without a user) becomes the user id,
+    /// `amr` the authentication methods, `auth_time` the authentication time and the
+    /// whole response the data.
+    pub async fn verify(&self, token: &str) -> Result<Identity, AuthError> {
+        let mut response = self.introspect(token).await?;
+        response.remove("active");
+        let user_id = ["sub", "username", "client_id"]
+            .iter()
+            .find_map(|claim| response.get(*claim).and_then(Value::as_str))
+            .ok_or(AuthError::InvalidToken)?
+            .to_string();
+        let amr = response
+            .get("amr")
+            .and_then(Value::as_array)
+            .map(|amr| {
+                amr.iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let auth_time = response.get("auth_time").and_then(Value::as_u64);
+        Ok(Identity {
+            user_id,
+            data: Value::Object(response),
+            amr,
+            auth_time,
+        })
+    }
+
+    fn check_claims(&self, response: &Map<String, Value>, current: u64) -> Result<(), AuthError> {
+        if let Some(issuer) = &self.issuer
+            && response.get("iss").and_then(Value::as_str) != Some(issuer.as_str())
+        {
+            return Err(AuthError::InvalidToken);
+        }
+        if let Some(audience) = &self.audience {
+            let matches = match response.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud == audience.as_str()),
+                _ => false,
+            };
+            if !matches {
+                return Err(AuthError::InvalidToken);
+            }
+        }
+        if response
+            .get("nbf")
+            .and_then(Value::as_u64)
+            .is_some_and(|nbf| nbf > current)
+        {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(())
+    }
+
+    fn cached(&self, key: &str, current: u64) -> Option<Map<String, Value>> {
+        let cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache
+            .get(key)
+            .filter(|(_, expires_at)| *expires_at > current)
+            .map(|(response, _)| response.clone())
+    }
+}
@@ -0,0 +1,919 @@
+//! SAML 2.0 service provider: SP metadata, AuthnRequests for the HTTP-Redirect and
+//! HTTP-POST bindings, and validation of the IdP's signed assertions.
+//!
+//! Signatures are checked against the certificates of the IdP metadata. Only the
+//! profile IdPs use in practice is accepted: one enveloped RSA-SHA256 signature over
+//! the assertion or the whole response, with exclusive canonicalization and SHA-256
+//! digests. Encrypted assertions are not supported.
+
+use crate::config::ConfigError;
+use crate::crypto::{hex, random_bytes};
+use crate::error::AuthError;
+use crate::identity::Identity;
+use crate::oauth_server::QUERY_VALUE;
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use percent_encoding::utf8_percent_encode;
+use roxmltree::{Document, Node, NodeId, NodeType};
+use rsa::RsaPublicKey;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+use x509_cert::Certificate;
+use x509_cert::der::{Decode, Encode};
+
+const METADATA: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+const PROTOCOL: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const DSIG: &str = "http://www.w3.org/2000/09/xmldsig#";
+const REDIRECT_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+const POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+const SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+/// Remembers the ids of consumed assertions until they expire, so each assertion
+/// logs in once.
+pub trait SamlReplayStore: Send + Sync {
+    /// Records `assertion_id`; `false` if it was already recorded and has not expired.
+    fn consume(
+        &self,
+        assertion_id: String,
+        expires_at: u64,
+    ) -> BoxFuture<'_, Result<bool, AuthError>>;
+}
+
+/// Process-local [`SamlReplayStore`], for tests and single-instance deployments.
+/// Expired entries are pruned on every call.
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySamlReplayStore {
+    consumed: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl InMemorySamlReplayStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SamlReplayStore for InMemorySamlReplayStore {
+    fn consume(
+        &self,
+        assertion_id: String,
+        expires_at: u64,
+    ) -> BoxFuture<'_, Result<bool, AuthError>> {
+        let fresh = now().map_err(AuthError::from).and_then(|current| {
+            let mut consumed = self.consumed.lock()?;
+            consumed.retain(|_, expires_at| *expires_at > current);
+            Ok(consumed.insert(assertion_id, expires_at).is_none())
+        });
+        Box::pin(async move { fresh })
+    }
+}
+
+/// The parts of an IdP's metadata a service provider needs.
+#[derive(Debug, Clone)]
+pub struct IdpMetadata {
+    pub entity_id: String,
+    /// `SingleSignOnService` location for the HTTP-Redirect binding.
+    pub sso_redirect_url: Option<String>,
+    /// `SingleSignOnService` location for the HTTP-POST binding.
+    pub sso_post_url: Option<String>,
+    signing_keys: Vec<RsaPublicKey>,
+}
+
+impl IdpMetadata {
+    /// Reads the first `EntityDescriptor` with an `IDPSSODescriptor`, as published by
+    /// the IdP (a single entity or an `EntitiesDescriptor` aggregate). Its signing
+    /// certificates must carry RSA keys.
+    pub fn parse(xml: &str) -> Result<Self, ConfigError> {
+        let document = Document::parse(xml).map_err(|err| ConfigError::Syntax {
+            format: "SAML metadata",
+            message: err.to_string(),
+        })?;
+        let descriptor = document
+            .descendants()
+            .find(|node| node.has_tag_name((METADATA, "IDPSSODescriptor")))
+            .ok_or_else(|| ConfigError::field("IDPSSODescriptor", "missing"))?;
+        let entity_id = descriptor
+            .parent_element()
+            .filter(|entity| entity.has_tag_name((METADATA, "EntityDescriptor")))
+            .and_then(|entity| entity.attribute("entityID"))
+            .ok_or_else(|| ConfigError::field("EntityDescriptor.entityID", "missing"))?;
+
+        let sso_url = |binding: &str| {
+            children(descriptor, METADATA, "SingleSignOnService")
+                .find(|service| service.attribute("Binding") == Some(binding))
+                .and_then(|service| service.attribute("Location"))
+                .map(str::to_string)
+        };
+        let mut signing_keys = Vec::new();
+        for key in children(descriptor, METADATA, "KeyDescriptor")
+            .filter(|key| key.attribute("use").is_none_or(|usage| usage == "signing"))
+        {
+            for certificate in key
+                .descendants()
+                .filter(|node| node.has_tag_name((DSIG, "X509Certificate")))
+            {
+                let field = "KeyDescriptor.X509Certificate";
+                let der = decode_base64(&text(certificate))
+                    .ok_or_else(|| ConfigError::field(field, "invalid base64"))?;
+                let spki = Certificate::from_der(&der)
+                    .and_then(|cert| cert.tbs_certificate.subject_public_key_info.to_der())
+                    .map_err(|err| ConfigError::field(field, err.to_string()))?;
+                let key = RsaPublicKey::from_public_key_der(&spki)
+                    .map_err(|_| ConfigError::field(field, "only RSA keys are supported"))?;
+                signing_keys.push(key);
+            }
+        }
+        if signing_keys.is_empty() {
+            return Err(ConfigError::field(
+                "KeyDescriptor",
+                "no signing certificate",
+            ));
+        }
+
+        Ok(Self {
+            entity_id: entity_id.to_string(),
+            sso_redirect_url: sso_url(REDIRECT_BINDING),
+            sso_post_url: sso_url(POST_BINDING),
+            signing_keys,
+        })
+    }
+}
+
+/// SAML protocol bindings for sending an [`AuthnRequest`] to the IdP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamlBinding {
+    Redirect,
+    Post,
+}
+
+/// An AuthnRequest on its way to the IdP. Keep `id` (e.g. in the session) and pass
+/// it back when the response arrives; it must match the response's `InResponseTo`.
+#[derive(Debug, Clone)]
+pub struct AuthnRequest {
+    pub id: String,
+    /// The IdP endpoint the request is addressed to.
+    pub destination: String,
+    pub xml: String,
+}
+
+impl AuthnRequest {
+    /// URL that sends the request with the HTTP-Redirect binding: the request
+    /// deflated, base64 encoded and passed as `SAMLRequest`.
+    pub fn redirect_url(&self, relay_state: Option<&str>) -> Result<String, AuthError> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(self.xml.as_bytes())
+            .map_err(AuthError::internal)?;
+        let deflated = encoder.finish().map_err(AuthError::internal)?;
+
+        let separator = if self.destination.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let mut url = format!(
+            "{}{separator}SAMLRequest={}",
+            self.destination,
+            utf8_percent_encode(&STANDARD.encode(deflated), QUERY_VALUE)
+        );
+        if let Some(relay_state) = relay_state {
+            url.push_str("&RelayState=");
+            url.extend(utf8_percent_encode(relay_state, QUERY_VALUE));
+        }
+        Ok(url)
+    }
+
+    /// HTML page that sends the request with the HTTP-POST binding, submitting itself
+    /// as soon as it loads.
+    pub fn post_form(&self, relay_state: Option<&str>) -> String {
+        let relay_state = relay_state
+            .map(|relay_state| {
+                format!(
+                    r#"<input type="hidden" name="RelayState" value="{}"/>"#,
+                    escape_attribute(relay_state)
+                )
+            })
+            .unwrap_or_default();
+        format!(
+            concat!(
+                r#"<!DOCTYPE html><html><body onload="document.forms[0].submit()">"#,
+                r#"<form method="post" action="{}">"#,
+                r#"<input type="hidden" name="SAMLRequest" value="{}"/>{}"#,
+                r#"<noscript><button type="submit">Continue</button></noscript>"#,
+                "</form></body></html>"
+            ),
+            escape_attribute(&self.destination),
+            STANDARD.encode(&self.xml),
+            relay_state
+        )
+    }
+}
+
+/// An assertion that passed validation, with the data needed for the replay check.
+struct ValidAssertion {
+    id: String,
+    expires_at: u64,
+    identity: Identity,
+}
+
+/// A SAML 2.0 service provider trusting one IdP.
+///
+/// Responses are accepted when a valid signature by the IdP covers the assertion,
+/// the issuer, audience, recipient and validity window match, and the assertion was
+/// not consumed before. The NameID becomes the user id; attributes, the session
+/// index, `auth_time` and `acr` go into the identity data. The reserved keys
+/// (`name_id_format`, `session_index`, `auth_time`, `acr`) always come from the
+/// assertion itself, never from an attribute mapped onto the same name.
+#[derive(Clone)]
+pub struct SamlServiceProvider {
+    entity_id: String,
+    acs_url: String,
+    idp: IdpMetadata,
+    clock_skew_secs: u64,
+    idp_initiated: bool,
+    attributes: HashMap<String, String>,
+    replay: Arc<dyn SamlReplayStore>,
+}
+
+impl SamlServiceProvider {
+    /// `entity_id` identifies this SP to the IdP and is the expected audience;
+    /// `acs_url` is the assertion consumer service receiving responses by POST.
+    pub fn new(entity_id: impl Into<String>, acs_url: impl Into<String>, idp: IdpMetadata) -> Self {
+        Self {
+            entity_id: entity_id.into(),
+            acs_url: acs_url.into(),
+            idp,
+            clock_skew_secs: 120,
+            idp_initiated: false,
+            attributes: HashMap::new(),
+            replay: Arc::new(InMemorySamlReplayStore::new()),
+        }
+    }
+
+    /// Tolerated clock difference with the IdP. Defaults to 2 minutes.
+    pub fn clock_skew(mut self, secs: u64) -> Self {
+        self.clock_skew_secs = secs;
+        self
+    }
+
+    /// Accepts unsolicited responses, sent without an AuthnRequest. Off by default,
+    /// as nothing ties such a response to a login the user started.
+    pub fn idp_initiated(mut self, allowed: bool) -> Self {
+        self.idp_initiated = allowed;
+        self
+    }
+
+    /// Stores the attribute `name` (or `FriendlyName`) under `key` in the identity
+    /// data. Unmapped attributes keep their name.
+    pub fn map_attribute(mut self, name: impl Into<String>, key: impl Into<String>) -> Self {
+        self.attributes.insert(name.into(), key.into());
+        self
+    }
+
+    /// Records consumed assertions in `store`, e.g. one shared by all instances.
+    pub fn replay_store<S>(mut self, store: S) -> Self
+    where
+        S: SamlReplayStore + 'static,
+    {
+        self.replay = Arc::new(store);
+        self
+    }
+
+    /// SP metadata to register with the IdP.
+    pub fn metadata(&self) -> String {
+        format!(
+            concat!(
+                r#"<md:EntityDescriptor xmlns:md="{}" entityID="{}">"#,
+                r#"<md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" "#,
+                r#"protocolSupportEnumeration="{}">"#,
+                "<md:NameIDFormat>urn:oasis:names:tc:SAML:1.1:nameid-format:unspecified</md:NameIDFormat>",
+                r#"<md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/>"#,
+                "</md:SPSSODescriptor></md:EntityDescriptor>"
+            ),
+            METADATA,
+            escape_attribute(&self.entity_id),
+            PROTOCOL,
+            POST_BINDING,
+            escape_attribute(&self.acs_url)
+        )
+    }
+
+    /// A new AuthnRequest for the IdP's SSO endpoint of `binding`. Fails with
+    /// [`AuthError::InternalError`] if the IdP metadata lists none.
+    pub fn authn_request(&self, binding: SamlBinding) -> Result<AuthnRequest, AuthError> {
+        let destination = match binding {
+            SamlBinding::Redirect => &self.idp.sso_redirect_url,
+            SamlBinding::Post => &self.idp.sso_post_url,
+        }
+        .clone()
+        .ok_or_else(|| {
+            AuthError::internal(format!(
+                "IdP metadata has no {binding:?} SingleSignOnService"
+            ))
+        })?;
+        let id = format!("_{}", hex(&random_bytes(16)));
+        let xml = format!(
+            concat!(
+                r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="{}" Version="2.0" "#,
+                r#"IssueInstant="{}" Destination="{}" AssertionConsumerServiceURL="{}" "#,
+                r#"ProtocolBinding="{}"><saml:Issuer>{}</saml:Issuer>"#,
+                r#"<samlp:NameIDPolicy AllowCreate="true"/></samlp:AuthnRequest>"#
+            ),
+            PROTOCOL,
+            ASSERTION,
+            id,
+            format_instant(now()?),
+            escape_attribute(&destination),
+            escape_attribute(&self.acs_url),
+            POST_BINDING,
+            escape_text(&self.entity_id)
+        );
+        Ok(AuthnRequest {
+            id,
+            destination,
+            xml,
+        })
+    }
+
+    /// Validates a base64 `SAMLResponse` posted to the ACS and maps its assertion to
+    /// an [`Identity`]. `request_id` is the id of the [`AuthnRequest`] it answers, or
+    /// `None` for an IdP-initiated response.
+    ///
+    /// Malformed responses fail with [`AuthError::InvalidInput`], everything else
+    /// with [`AuthError::CredentialRejected`].
+    pub async fn validate(
+        &self,
+        saml_response: &str,
+        request_id: Option<&str>,
+    ) -> Result<Identity, AuthError> {
+        let assertion = self.valid_assertion(saml_response, request_id)?;
+        if !self
+            .replay
+            .consume(assertion.id, assertion.expires_at)
+            .await?
+        {
+            return Err(AuthError::CredentialRejected);
+        }
+        Ok(assertion.identity)
+    }
+
+    fn valid_assertion(
+        &self,
+        saml_response: &str,
+        request_id: Option<&str>,
+    ) -> Result<ValidAssertion, AuthError> {
+        let xml = decode_base64(saml_response)
+            .and_then(|xml| String::from_utf8(xml).ok())
+            .ok_or(AuthError::InvalidInput)?;
+        // DTDs are rejected by the parser, which rules out entity expansion attacks.
+        let document = Document::parse(&xml).map_err(|_| AuthError::InvalidInput)?;
+        let response = document.root_element();
+        if !response.has_tag_name((PROTOCOL, "Response")) {
+            return Err(AuthError::InvalidInput);
+        }
+        let current = now()?;
+        let skew = self.clock_skew_secs;
+        let rejected = || AuthError::CredentialRejected;
+
+        let status = child(response, PROTOCOL, "Status")
+            .and_then(|status| child(status, PROTOCOL, "StatusCode"))
+            .and_then(|code| code.attribute("Value"));
+        if status != Some(SUCCESS) {
+            return Err(rejected());
+        }
+        if response
+            .attribute("Destination")
+            .is_some_and(|destination| destination != self.acs_url)
+        {
+            return Err(rejected());
+        }
+        if let Some(in_response_to) = response.attribute("InResponseTo")
+            && Some(in_response_to) != request_id
+        {
+            return Err(rejected());
+        }
+
+        // Exactly one assertion, directly under the response, so the signed element
+        // is the one read below and cannot be swapped by wrapping.
+        let mut assertions = document.descendants().filter(|node| {
+            node.has_tag_name((ASSERTION, "Assertion"))
+                || node.has_tag_name((ASSERTION, "EncryptedAssertion"))
+        });
+        let assertion = match (assertions.next(), assertions.next()) {
+            (Some(assertion), None)
+                if assertion.has_tag_name((ASSERTION, "Assertion"))
+                    && assertion.parent() == Some(response) =>
+            {
+                assertion
+            }
+            _ => return Err(rejected()),
+        };
+        if child(assertion, DSIG, "Signature").is_some() {
+            self.verify_signature(assertion)?;
+        } else {
+            self.verify_signature(response)?;
+        }
+
+        if child(assertion, ASSERTION, "Issuer").map(text).as_deref()
+            != Some(self.idp.entity_id.as_str())
+        {
+            return Err(rejected());
+        }
+
+        let conditions = child(assertion, ASSERTION, "Conditions").ok_or_else(rejected)?;
+        if let Some(not_before) = conditions.attribute("NotBefore")
+            && parse_instant(not_before).ok_or_else(rejected)? > current.saturating_add(skew)
+        {
+            return Err(rejected());
+        }
+        if let Some(not_on_or_after) = conditions.attribute("NotOnOrAfter")
+            && parse_instant(not_on_or_after)
+                .ok_or_else(rejected)?
+                .saturating_add(skew)
+                <= current
+        {
+            return Err(rejected());
+        }
+        let mut restrictions = children(conditions, ASSERTION, "AudienceRestriction").peekable();
+        if restrictions.peek().is_none() {
+            return Err(rejected());
+        }
+        for restriction in restrictions {
+            if !children(restriction, ASSERTION, "Audience")
+                .any(|audience| text(audience) == self.entity_id)
+            {
+                return Err(rejected());
+            }
+        }
+
+        let subject = child(assertion, ASSERTION, "Subject").ok_or_else(rejected)?;
+        let name_id = child(subject, ASSERTION, "NameID")
+            .map(text)
+            .map(|name_id| name_id.trim().to_string())
+            .filter(|name_id| !name_id.is_empty())
+            .ok_or_else(rejected)?;
+        if request_id.is_none() && !self.idp_initiated {
+            return Err(rejected());
+        }
+        let expires_at = children(subject, ASSERTION, "SubjectConfirmation")
+            .filter(|confirmation| confirmation.attribute("Method") == Some(BEARER))
+            .filter_map(|confirmation| child(confirmation, ASSERTION, "SubjectConfirmationData"))
+            .filter(|data| data.attribute("Recipient") == Some(self.acs_url.as_str()))
+            .filter(|data| data.attribute("InResponseTo") == request_id)
+            .filter_map(|data| data.attribute("NotOnOrAfter").and_then(parse_instant))
+            .find(|not_on_or_after| not_on_or_after.saturating_add(skew) > current)
+            .ok_or_else(rejected)?;
+
+        Ok(ValidAssertion {
+            id: assertion.attribute("ID").ok_or_else(rejected)?.to_string(),
+            expires_at: expires_at.saturating_add(skew),
+            identity: Identity {
+                user_id: name_id,
+                data: Value::Object(self.assertion_data(assertion)),
+                amr: Vec::new(),
+                auth_time: child(assertion, ASSERTION, "AuthnStatement")
+                    .and_then(|statement| statement.attribute("AuthnInstant"))
+                    .and_then(parse_instant),
+            },
+        })
+    }
+
+    fn assertion_data(&self, assertion: Node) -> Map<String, Value> {
+        let mut data = Map::new();
+        for attribute in children(assertion, ASSERTION, "AttributeStatement")
+            .flat_map(|statement| children(statement, ASSERTION, "Attribute"))
+        {
+            let Some(name) = attribute.attribute("Name") else {
+                continue;
+            };
+            let key = self
+                .attributes
+                .get(name)
+                .or_else(|| {
+                    attribute
+                        .attribute("FriendlyName")
+                        .and_then(|friendly_name| self.attributes.get(friendly_name))
+                })
+                .map_or(name, String::as_str);
+            let mut values: Vec<Value> = children(attribute, ASSERTION, "AttributeValue")
+                .map(|value| Value::from(text(value)))
+                .collect();
+            let value = match values.len() {
+                1 => values.remove(0),
+                _ => Value::Array(values),
+            };
+            data.insert(key.to_string(), value);
+        }
+
+        // Written last so an attribute cannot stand in for them.
+        for key in ["name_id_format", "session_index", "auth_time", "acr"] {
+            data.remove(key);
+        }
+        if let Some(format) = child(assertion, ASSERTION, "Subject")
+            .and_then(|subject| child(subject, ASSERTION, "NameID"))
+            .and_then(|name_id| name_id.attribute("Format"))
+        {
+            data.insert("name_id_format".to_string(), Value::from(format));
+        }
+        if let Some(statement) = child(assertion, ASSERTION, "AuthnStatement") {
+            if let Some(session_index) = statement.attribute("SessionIndex") {
+                data.insert("session_index".to_string(), Value::from(session_index));
+            }
+            if let Some(auth_time) = statement.attribute("AuthnInstant").and_then(parse_instant) {
+                data.insert("auth_time".to_string(), Value::from(auth_time));
+            }
+            if let Some(acr) = statement
+                .descendants()
+                .find(|node| node.has_tag_name((ASSERTION, "AuthnContextClassRef")))
+                .map(text)
+            {
+                data.insert("acr".to_string(), Value::from(acr.trim()));
+            }
+        }
+        data
+    }
+
+    /// Checks the enveloped signature of `signed` against the IdP's keys.
+    fn verify_signature(&self, signed: Node) -> Result<(), AuthError> {
+        let rejected = || AuthError::CredentialRejected;
+        let signature = child(signed, DSIG, "Signature").ok_or_else(rejected)?;
+        let signed_info = child(signature, DSIG, "SignedInfo").ok_or_else(rejected)?;
+
+        let canonicalization = child(signed_info, DSIG, "CanonicalizationMethod");
+        if algorithm(canonicalization) != Some(EXC_C14N)
+            || algorithm(child(signed_info, DSIG, "SignatureMethod")) != Some(RSA_SHA256)
+        {
+            return Err(rejected());
+        }
+        let mut references = children(signed_info, DSIG, "Reference");
+        let reference = match (references.next(), references.next()) {
+            (Some(reference), None) => reference,
+            _ => return Err(rejected()),
+        };
+        let id = signed.attribute("ID").ok_or_else(rejected)?;
+        if reference
+            .attribute("URI")
+            .and_then(|uri| uri.strip_prefix('#'))
+            != Some(id)
+            || algorithm(child(reference, DSIG, "DigestMethod")) != Some(SHA256)
+        {
+            return Err(rejected());
+        }
+
+        let mut inclusive_prefixes = Vec::new();
+        let mut canonicalized = false;
+        for transform in child(reference, DSIG, "Transforms")
+            .into_iter()
+            .flat_map(|transforms| children(transforms, DSIG, "Transform"))
+        {
+            match transform.attribute("Algorithm") {
+                Some(ENVELOPED_SIGNATURE) => {}
+                Some(EXC_C14N) => {
+                    inclusive_prefixes = prefix_list(transform);
+                    canonicalized = true;
+                }
+                _ => return Err(rejected()),
+            }
+        }
+        if !canonicalized {
+            return Err(rejected());
+        }
+
+        let digest = child(reference, DSIG, "DigestValue")
+            .and_then(|digest| decode_base64(&text(digest)))
+            .ok_or_else(rejected)?;
+        let content = canonicalize(signed, Some(signature.id()), &inclusive_prefixes);
+        if !bool::from(Sha256::digest(content.as_bytes()).ct_eq(digest.as_slice())) {
+            return Err(rejected());
+        }
+
+        let signature = child(signature, DSIG, "SignatureValue")
+            .and_then(|value| decode_base64(&text(value)))
+            .and_then(|value| Signature::try_from(value.as_slice()).ok())
+            .ok_or_else(rejected)?;
+        let signed_info = canonicalize(
+            signed_info,
+            None,
+            &canonicalization.map(prefix_list).unwrap_or_default(),
+        );
+        self.idp
+            .signing_keys
+            .iter()
+            .find(|key| {
+                VerifyingKey::<Sha256>::new((*key).clone())
+                    .verify(signed_info.as_bytes(), &signature)
+                    .is_ok()
+            })
+            .map(|_| ())
+            .ok_or_else(rejected)
+    }
+}
+
+fn algorithm<'a>(method: Option<Node<'a, '_>>) -> Option<&'a str> {
+    method.and_then(|method| method.attribute("Algorithm"))
+}
+
+fn child<'a, 'input>(
+    node: Node<'a, 'input>,
+    namespace: &str,
+    name: &str,
+) -> Option<Node<'a, 'input>> {
+    children(node, namespace, name).next()
+}
+
+/// Every descendant text node of `node`, joined: the text its canonical form, and
+/// so the signature, covers. `Node::text` stops at the first comment, which would
+/// read `alice<!---->@example.com` as `alice`.
+fn text(node: Node) -> String {
+    node.descendants()
+        .filter(|node| node.is_text())
+        .filter_map(|node| node.text())
+        .collect()
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    namespace: &str,
+    name: &str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.has_tag_name((namespace, name)))
+}
+
+/// Prefixes of the `InclusiveNamespaces` of an exclusive canonicalization method.
+fn prefix_list(method: Node) -> Vec<String> {
+    child(method, EXC_C14N, "InclusiveNamespaces")
+        .and_then(|inclusive| inclusive.attribute("PrefixList"))
+        .map(|prefixes| prefixes.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Exclusive XML canonicalization without comments of the subtree at `node`,
+/// leaving out the `excluded` element as the enveloped-signature transform does.
+fn canonicalize(node: Node, excluded: Option<NodeId>, inclusive_prefixes: &[String]) -> String {
+    let mut out = String::new();
+    write_element(node, excluded, inclusive_prefixes, &[], &mut out);
+    out
+}
+
+/// Writes `node` canonicalized; `rendered` holds the namespace declarations in
+/// effect from output ancestors, as (prefix, uri) with `""` for the default.
+fn write_element(
+    node: Node,
+    excluded: Option<NodeId>,
+    inclusive_prefixes: &[String],
+    rendered: &[(String, String)],
+    out: &mut String,
+) {
+    let input = node.document().input_text();
+    let name = qualified_name(&input[node.range().start + 1..]);
+    let attributes: Vec<_> = node
+        .attributes()
+        .map(|attribute| (&input[attribute.range_qname()], attribute))
+        .collect();
+
+    // Namespaces visibly used by the element and its attributes, plus the
+    // inclusive ones, unless an output ancestor already declared them.
+    let mut used: Vec<&str> = vec![prefix(name)];
+    used.extend(
+        attributes
+            .iter()
+            .map(|(name, _)| prefix(name))
+            .filter(|prefix| !prefix.is_empty()),
+    );
+    used.extend(
+        inclusive_prefixes
+            .iter()
+            .map(|prefix| match prefix.as_str() {
+                "#default" => "",
+                prefix => prefix,
+            }),
+    );
+    let mut declarations: Vec<(String, String)> = Vec::new();
+    for prefix in used {
+        if prefix == "xml" || declarations.iter().any(|(declared, _)| declared == prefix) {
+            continue;
+        }
+        let uri = match prefix {
+            "" => node.default_namespace().unwrap_or_default(),
+            prefix => match node.lookup_namespace_uri(Some(prefix)) {
+                Some(uri) => uri,
+                None => continue,
+            },
+        };
+        let in_effect = rendered
+            .iter()
+            .rev()
+            .find(|(declared, _)| declared == prefix)
+            .map_or("", |(_, uri)| uri.as_str());
+        if uri != in_effect {
+            declarations.push((prefix.to_string(), uri.to_string()));
+        }
+    }
+    declarations.sort();
+
+    out.push('<');
+    out.push_str(name);
+    for (prefix, uri) in &declarations {
+        match prefix.as_str() {
+            "" => out.push_str(" xmlns=\""),
+            prefix => {
+                out.push_str(" xmlns:");
+                out.push_str(prefix);
+                out.push_str("=\"");
+            }
+        }
+        out.push_str(&escape_c14n_attribute(uri));
+        out.push('"');
+    }
+    let mut attributes = attributes;
+    attributes.sort_by_key(|(_, attribute)| {
+        (attribute.namespace().unwrap_or_default(), attribute.name())
+    });
+    for (name, attribute) in attributes {
+        out.push(' ');
+        out.push_str(name);
+        out.push_str("=\"");
+        out.push_str(&escape_c14n_attribute(attribute.value()));
+        out.push('"');
+    }
+    out.push('>');
+
+    let mut rendered = rendered.to_vec();
+    rendered.extend(declarations);
+    for child in node.children() {
+        if Some(child.id()) == excluded {
+            continue;
+        }
+        match child.node_type() {
+            NodeType::Element => write_element(child, excluded, inclusive_prefixes, &rendered, out),
+            NodeType::Text => {
+                let text = child.text().unwrap_or_default();
+                out.push_str(
+                    &text
+                        .replace('&', "&amp;")
+                        .replace('<', "&lt;")
+                        .replace('>', "&gt;")
+                        .replace('\r', "&#xD;"),
+                );
+            }
+            NodeType::PI => {
+                if let Some(pi) = child.pi() {
+                    out.push_str("<?");
+                    out.push_str(pi.target);
+                    if let Some(value) = pi.value {
+                        out.push(' ');
+                        out.push_str(value);
+                    }
+                    out.push_str("?>");
+                }
+            }
+            NodeType::Comment | NodeType::Root => {}
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(name);
+    out.push('>');
+}
+
+/// The qualified name at the start of `text`, as written in the document.
+fn qualified_name(text: &str) -> &str {
+    let end = text
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+fn prefix(qualified_name: &str) -> &str {
+    qualified_name
+        .split_once(':')
+        .map_or("", |(prefix, _)| prefix)
+}
+
+fn escape_c14n_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}
+
+fn escape_text(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_attribute(value: &str) -> String {
+    escape_text(value).replace('"', "&quot;")
+}
+
+/// Base64 as embedded in XML, where line breaks are common.
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let value: String = value.split_whitespace().collect();
+    STANDARD.decode(value).ok()
+}
+
+/// Seconds since the epoch of an `xs:dateTime` such as `2024-05-01T12:00:00.000Z`.
+fn parse_instant(value: &str) -> Option<u64> {
+    let (date, time) = value.split_once('T')?;
+    let (time, offset) = match time.strip_suffix('Z') {
+        Some(time) => (time, 0),
+        None => {
+            let split = time.rfind(['+', '-'])?;
+            let (hours, minutes) = time[split + 1..].split_once(':')?;
+            let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+            if !(0..=23).contains(&hours) || !(0..=59).contains(&minutes) {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            let offset = if &time[split..=split] == "-" {
+                -offset
+            } else {
+                offset
+            };
+            (&time[..split], offset)
+        }
+    };
+
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':');
+    let (hour, minute) = (
+        time.next()?.parse::<i64>().ok()?,
+        time.next()?.parse::<i64>().ok()?,
+    );
+    let second = time.next()?.split('.').next()?.parse::<i64>().ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..=23).contains(&hour)
+        || !(0..=59).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+
+    // Days from the civil date, counting years from March so leap days come last.
+    // The year is unbounded, so everything that scales with it is checked.
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era
+        .checked_mul(146_097)?
+        .checked_add(day_of_era - 719_468)?;
+    let secs = days
+        .checked_mul(86_400)?
+        .checked_add(hour * 3600 + minute * 60 + second - offset)?;
+    u64::try_from(secs).ok()
+}
+
+/// `xs:dateTime` in UTC of seconds since the epoch, the inverse of [`parse_instant`].
+fn format_instant(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let day_of_era = (days + 719_468).rem_euclid(146_097);
+    let era = (days + 719_468).div_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
@@ -0,0 +1,141 @@
+use crate::config::ConfigError;
+use jsonwebtoken::Algorithm;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+/// Minimum estimated entropy of an HMAC key, in bits.
+const MIN_HMAC_ENTROPY_BITS: f64 = 128.0;
+
+/// Key material such as a JWT signing secret or an OAuth client secret.
+///
+/// The contents are wiped from memory on drop and never printed by `Debug`. Use
+/// [`expose`](Secret::expose) at the point the raw value is actually needed.
+#[derive(Clone, Default)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Reads the secret from environment variable `var`.
+    pub fn from_env(var: &str) -> Result<Self, ConfigError> {
+        std::env::var(var)
+            .map(Self)
+            .map_err(|err| ConfigError::InvalidField {
+                field: var.to_string(),
+                message: err.to_string(),
+            })
+    }
+
+    /// Reads the secret from a file, e.g. a mounted Docker or Kubernetes secret. A
+    /// single trailing newline is stripped.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let mut value = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if value.ends_with('\n') {
+            value.pop();
+            if value.ends_with('\r') {
+                value.pop();
+            }
+        }
+        Ok(Self(value))
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Checks that the secret is usable as an HMAC key for `algorithm`: at least as
+    /// long as the hash output (RFC 7518 section 3.2) and not obviously low entropy,
+    /// such as a repeated character or a short dictionary word padded out.
+    pub fn check_hmac_strength(&self, algorithm: Algorithm) -> Result<(), String> {
+        let min_len = match algorithm {
+            Algorithm::HS384 => 48,
+            Algorithm::HS512 => 64,
+            _ => 32,
+        };
+        if self.0.len() < min_len {
+            return Err(format!(
+                "must be at least {min_len} bytes for {algorithm:?}, got {}",
+                self.0.len()
+            ));
+        }
+        let bits = estimated_entropy_bits(self.0.as_bytes());
+        if bits < MIN_HMAC_ENTROPY_BITS {
+            return Err(format!(
+                "has too little entropy (about {bits:.0} bits, need {MIN_HMAC_ENTROPY_BITS:.0}); use a random key"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Shannon entropy of the byte distribution times the length. This is an upper bound
+/// on the real entropy, so it only catches keys that are plainly weak.
+fn estimated_entropy_bits(bytes: &[u8]) -> f64 {
+    let mut counts = HashMap::new();
+    for byte in bytes {
+        *counts.entry(byte).or_insert(0usize) += 1;
+    }
+    let len = bytes.len() as f64;
+    let per_byte: f64 = counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum();
+    per_byte * len
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Compares in constant time so the comparison does not leak how much of a guessed
+/// secret was right.
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_bytes().ct_eq(other.0.as_bytes()).into()
+    }
+}
+
+impl Eq for Secret {}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
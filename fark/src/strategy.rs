@@ -1,12 +1,18 @@
-
-use crate::input::AuthInput;
-use crate::error::{AuthError};
+use crate::context::AuthContext;
+use crate::error::AuthError;
 use crate::identity::Identity;
+use crate::input::AuthInput;
 use std::future::Future;
 use std::pin::Pin;
-  
+
 pub type Strategy = Box<
-    dyn Fn(AuthInput) -> Pin<Box<dyn Future<Output = Result<Identity, AuthError>> + Send>>
+    dyn Fn(
+            AuthInput,
+            AuthContext,
+        ) -> Pin<Box<dyn Future<Output = Result<Identity, AuthError>> + Send>>
         + Send
         + Sync,
->;
\ No newline at end of file
+>;
+
+/// Boxed future returned by the storage traits, so they stay object safe.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
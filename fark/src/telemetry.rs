@@ -0,0 +1,72 @@
+//! Optional instrumentation hooks. Every function here compiles to nothing unless the
+//! matching feature is enabled.
+
+use crate::error::AuthError;
+use std::time::Instant;
+
+/// Records the outcome of an operation on the current span and emits an event.
+/// Credential failures are expected traffic and logged at `info`; anything else at `warn`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn record_outcome<T>(subject: Option<&str>, result: &Result<T, AuthError>) {
+    #[cfg(feature = "tracing")]
+    {
+        let span = tracing::Span::current();
+        if let Some(subject) = subject {
+            span.record("subject", subject);
+        }
+        match result {
+            Ok(_) => tracing::debug!("succeeded"),
+            Err(err) => {
+                span.record("error", err.code());
+                if err.is_credential_error() {
+                    tracing::info!(error = err.code(), "rejected");
+                } else {
+                    tracing::warn!(error = err.code(), "failed");
+                }
+            }
+        }
+    }
+}
+
+/// Counts and times one `authenticate` call as `fark_authenticate_total` and
+/// `fark_authenticate_duration_seconds`, labelled by strategy (`unknown` for names
+/// that are not registered) and outcome.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record_authenticate<T>(
+    strategy: &str,
+    started: Instant,
+    result: &Result<T, AuthError>,
+) {
+    #[cfg(feature = "metrics")]
+    {
+        let outcome = outcome(result);
+        let labels = [
+            ("strategy", strategy.to_string()),
+            ("outcome", outcome.to_string()),
+        ];
+        metrics::counter!("fark_authenticate_total", &labels).increment(1);
+        metrics::histogram!("fark_authenticate_duration_seconds", &labels)
+            .record(started.elapsed().as_secs_f64());
+    }
+}
+
+/// Counts one `verify_jwt` call as `fark_verify_jwt_total`, labelled by outcome and
+/// error kind.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record_verify<T>(result: &Result<T, AuthError>) {
+    #[cfg(feature = "metrics")]
+    {
+        let error = result.as_ref().err().map_or("none", AuthError::code);
+        metrics::counter!(
+            "fark_verify_jwt_total",
+            "outcome" => outcome(result),
+            "error" => error
+        )
+        .increment(1);
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn outcome<T>(result: &Result<T, AuthError>) -> &'static str {
+    if result.is_ok() { "success" } else { "failure" }
+}
@@ -0,0 +1,303 @@
+use crate::config::number;
+use crate::error::AuthError;
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// How many failures a key may accumulate and how long it is locked afterwards.
+///
+/// The first `free_attempts` failures are not delayed. Each failure after that locks
+/// the key for `base_delay * 2^n` seconds, capped at `max_delay`. Failures are
+/// forgotten `reset_after` seconds after the last one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThrottlePolicy {
+    #[serde(deserialize_with = "number")]
+    pub free_attempts: u32,
+    #[serde(deserialize_with = "number")]
+    pub base_delay: u64,
+    #[serde(deserialize_with = "number")]
+    pub max_delay: u64,
+    #[serde(deserialize_with = "number")]
+    pub reset_after: u64,
+}
+
+impl ThrottlePolicy {
+    /// Per-account defaults: 5 free attempts, then 1s, 2s, 4s... up to 15 minutes.
+    pub fn account() -> Self {
+        Self {
+            free_attempts: 5,
+            base_delay: 1,
+            max_delay: 900,
+            reset_after: 3600,
+        }
+    }
+
+    /// Per-IP defaults: more lenient, since many users can share an address.
+    pub fn ip() -> Self {
+        Self {
+            free_attempts: 20,
+            base_delay: 1,
+            max_delay: 900,
+            reset_after: 3600,
+        }
+    }
+
+    /// Seconds a key is locked once it has `failures` counted attempts.
+    pub fn lockout(&self, failures: u32) -> u64 {
+        if failures <= self.free_attempts {
+            return 0;
+        }
+        let exponent = (failures - self.free_attempts - 1).min(63);
+        self.base_delay
+            .saturating_mul(1u64 << exponent)
+            .min(self.max_delay)
+    }
+}
+
+/// Failure count and lockout of one throttle key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThrottleState {
+    pub failures: u32,
+    pub locked_until: u64,
+}
+
+/// Counter storage for [`Throttle`]. Share one store between instances (e.g. Redis)
+/// to throttle across a cluster.
+pub trait ThrottleStore: Send + Sync {
+    fn get(&self, key: &str) -> BoxFuture<'_, Result<ThrottleState, AuthError>>;
+
+    /// Atomically reserves an attempt before the strategy runs. Fails with
+    /// [`AuthError::TooManyAttempts`] while the key is locked; otherwise increments
+    /// the count, keeps it for `policy.reset_after` seconds, locks the key for
+    /// [`policy.lockout`](ThrottlePolicy::lockout) of the new count and returns the
+    /// new state.
+    fn attempt(
+        &self,
+        key: &str,
+        policy: ThrottlePolicy,
+    ) -> BoxFuture<'_, Result<ThrottleState, AuthError>>;
+
+    /// Gives back an attempt that did not fail on credentials: decrements the count
+    /// and lifts the lock if it is still the one `reserved` set.
+    fn release(&self, key: &str, reserved: ThrottleState) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    fn reset(&self, key: &str) -> BoxFuture<'_, Result<(), AuthError>>;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    state: ThrottleState,
+    expire_at: u64,
+}
+
+/// Process-local [`ThrottleStore`].
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryThrottleStore {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl InMemoryThrottleStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ThrottleStore for InMemoryThrottleStore {
+    fn get(&self, key: &str) -> BoxFuture<'_, Result<ThrottleState, AuthError>> {
+        let state = now().map_err(AuthError::from).and_then(|current| {
+            self.entries
+                .lock()
+                .map(|entries| {
+                    entries
+                        .get(key)
+                        .filter(|entry| entry.expire_at > current)
+                        .map(|entry| entry.state)
+                        .unwrap_or_default()
+                })
+                .map_err(AuthError::from)
+        });
+        Box::pin(async move { state })
+    }
+
+    fn attempt(
+        &self,
+        key: &str,
+        policy: ThrottlePolicy,
+    ) -> BoxFuture<'_, Result<ThrottleState, AuthError>> {
+        let state = now().map_err(AuthError::from).and_then(|current| {
+            let mut entries = self.entries.lock()?;
+            entries.retain(|_, entry| entry.expire_at > current);
+            let entry = entries.entry(key.to_string()).or_insert(Entry {
+                state: ThrottleState::default(),
+                expire_at: current,
+            });
+            if entry.state.locked_until > current {
+                return Err(AuthError::TooManyAttempts {
+                    retry_after: entry.state.locked_until - current,
+                });
+            }
+            entry.state.failures = entry.state.failures.saturating_add(1);
+            let lockout = policy.lockout(entry.state.failures);
+            if lockout > 0 {
+                entry.state.locked_until = current.saturating_add(lockout);
+            }
+            entry.expire_at = current
+                .saturating_add(policy.reset_after)
+                .max(entry.state.locked_until);
+            Ok(entry.state)
+        });
+        Box::pin(async move { state })
+    }
+
+    fn release(&self, key: &str, reserved: ThrottleState) -> BoxFuture<'_, Result<(), AuthError>> {
+        let released = self
+            .entries
+            .lock()
+            .map(|mut entries| {
+                if let Some(entry) = entries.get_mut(key) {
+                    entry.state.failures = entry.state.failures.saturating_sub(1);
+                    if entry.state.locked_until == reserved.locked_until {
+                        entry.state.locked_until = 0;
+                    }
+                }
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { released })
+    }
+
+    fn reset(&self, key: &str) -> BoxFuture<'_, Result<(), AuthError>> {
+        let reset = self
+            .entries
+            .lock()
+            .map(|mut entries| {
+                entries.remove(key);
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { reset })
+    }
+}
+
+/// Brute-force protection around strategy execution, keyed by account identifier
+/// and client IP. Register with [`Fark::with_throttle`](crate::Fark::with_throttle).
+///
+/// Inputs that name no account, such as a PIN, are also counted per strategy, so
+/// guessing stays throttled without a client IP. Such failures can lock the strategy
+/// for everyone; tune that with [`strategy_policy`](Self::strategy_policy).
+#[derive(Clone)]
+pub struct Throttle {
+    store: Arc<dyn ThrottleStore>,
+    account_policy: ThrottlePolicy,
+    ip_policy: ThrottlePolicy,
+    strategy_policy: ThrottlePolicy,
+}
+
+impl Throttle {
+    pub fn new<S>(store: S) -> Self
+    where
+        S: ThrottleStore + 'static,
+    {
+        Self {
+            store: Arc::new(store),
+            account_policy: ThrottlePolicy::account(),
+            ip_policy: ThrottlePolicy::ip(),
+            strategy_policy: ThrottlePolicy::ip(),
+        }
+    }
+
+    pub fn account_policy(mut self, policy: ThrottlePolicy) -> Self {
+        self.account_policy = policy;
+        self
+    }
+
+    pub fn ip_policy(mut self, policy: ThrottlePolicy) -> Self {
+        self.ip_policy = policy;
+        self
+    }
+
+    /// Policy for inputs without an account, counted per strategy. Defaults to the
+    /// lenient [`ThrottlePolicy::ip`].
+    pub fn strategy_policy(mut self, policy: ThrottlePolicy) -> Self {
+        self.strategy_policy = policy;
+        self
+    }
+
+    fn keys(
+        &self,
+        strategy: &str,
+        account: Option<&str>,
+        ip: Option<IpAddr>,
+    ) -> Vec<(String, ThrottlePolicy)> {
+        let mut keys = Vec::with_capacity(2);
+        match account {
+            Some(account) => keys.push((format!("account:{account}"), self.account_policy)),
+            None => keys.push((format!("strategy:{strategy}"), self.strategy_policy)),
+        }
+        if let Some(ip) = ip {
+            keys.push((format!("ip:{ip}"), self.ip_policy));
+        }
+        keys
+    }
+
+    /// Reserves an attempt on every key of `strategy` before it runs, so concurrent
+    /// guesses cannot all pass a check made before any of them fails. Fails with
+    /// [`AuthError::TooManyAttempts`] while any key is locked.
+    ///
+    /// The reservation counts as a failure unless it is handed back with
+    /// [`record_success`](Self::record_success) or [`release`](Self::release).
+    pub async fn attempt(
+        &self,
+        strategy: &str,
+        account: Option<&str>,
+        ip: Option<IpAddr>,
+    ) -> Result<ThrottleAttempt, AuthError> {
+        let mut reserved = ThrottleAttempt {
+            account: account.map(|account| format!("account:{account}")),
+            keys: Vec::with_capacity(2),
+        };
+        for (key, policy) in self.keys(strategy, account, ip) {
+            match self.store.attempt(&key, policy).await {
+                Ok(state) => reserved.keys.push((key, state)),
+                Err(err) => {
+                    self.release(reserved).await?;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(reserved)
+    }
+
+    /// Gives the reservation back, for attempts that failed for reasons other than
+    /// the credentials.
+    pub async fn release(&self, attempt: ThrottleAttempt) -> Result<(), AuthError> {
+        for (key, state) in attempt.keys {
+            self.store.release(&key, state).await?;
+        }
+        Ok(())
+    }
+
+    /// Clears the account's failures. The IP and strategy reservations are only given
+    /// back, so one valid login cannot reset an attacker's budget.
+    pub async fn record_success(&self, attempt: ThrottleAttempt) -> Result<(), AuthError> {
+        for (key, state) in attempt.keys {
+            if attempt.account.as_ref() == Some(&key) {
+                self.store.reset(&key).await?;
+            } else {
+                self.store.release(&key, state).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Attempt reserved by [`Throttle::attempt`]. Dropping it keeps the attempt counted
+/// as a failure.
+#[derive(Debug)]
+#[must_use]
+pub struct ThrottleAttempt {
+    account: Option<String>,
+    keys: Vec<(String, ThrottleState)>,
+}
@@ -1,10 +1,9 @@
-
+use crate::error::TimeError;
 use std::time::{SystemTime, UNIX_EPOCH};
-use crate::error::{TimeError};
 
-pub(crate) fn now() -> Result<u64, TimeError>  {
+pub(crate) fn now() -> Result<u64, TimeError> {
     let time = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map_err(|_| TimeError::TimeGenError)?;
     Ok(time.as_secs())
-}  
\ No newline at end of file
+}
@@ -0,0 +1,283 @@
+use crate::config::ConfigError;
+use crate::crypto::{base32_decode, base32_encode, random_bytes};
+use crate::error::AuthError;
+use crate::hotp::{code_digits, hotp_code};
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use hmac::{Hmac, Mac};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+
+/// HMAC algorithm used to derive TOTP and HOTP codes. Most authenticator apps only
+/// support `Sha1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TotpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl TotpAlgorithm {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TotpAlgorithm::Sha1 => "SHA1",
+            TotpAlgorithm::Sha256 => "SHA256",
+            TotpAlgorithm::Sha512 => "SHA512",
+        }
+    }
+
+    pub(crate) fn hmac(&self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        match self {
+            TotpAlgorithm::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("hmac accepts any key");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            TotpAlgorithm::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            TotpAlgorithm::Sha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("hmac accepts any key");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+/// Shared secret between the server and the user's authenticator or hardware token.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    /// Generates a fresh 160-bit secret, the size recommended by RFC 4226.
+    pub fn generate() -> Self {
+        Self(random_bytes(20))
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn from_base32(encoded: &str) -> Result<Self, AuthError> {
+        match base32_decode(encoded) {
+            Some(bytes) if !bytes.is_empty() => Ok(Self(bytes)),
+            _ => Err(AuthError::InvalidInput),
+        }
+    }
+
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for TotpSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TotpSecret(..)")
+    }
+}
+
+/// Everything a user needs to add the account to an authenticator app.
+#[derive(Debug, Clone)]
+pub struct TotpEnrollment {
+    pub secret: TotpSecret,
+    pub uri: String,
+}
+
+/// Largest accepted clock skew, in time steps either side of the current one.
+const MAX_SKEW: u64 = 10;
+
+/// Remembers the last accepted time step of each account, so a code is accepted once.
+pub trait TotpStore: Send + Sync {
+    /// Records `step` as the last accepted step for `account` if it is later than the
+    /// stored one, returning whether it did. Implementations must compare and update
+    /// atomically, so concurrent requests cannot both use the same code.
+    fn advance(&self, account: &str, step: u64) -> BoxFuture<'_, Result<bool, AuthError>>;
+}
+
+/// Process-local [`TotpStore`], for tests and single-instance deployments.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryTotpStore {
+    last_steps: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl InMemoryTotpStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TotpStore for InMemoryTotpStore {
+    fn advance(&self, account: &str, step: u64) -> BoxFuture<'_, Result<bool, AuthError>> {
+        let advanced = self
+            .last_steps
+            .lock()
+            .map(|mut last_steps| {
+                if last_steps.get(account).is_some_and(|last| step <= *last) {
+                    return false;
+                }
+                last_steps.insert(account.to_string(), step);
+                true
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { advanced })
+    }
+}
+
+/// RFC 6238 time-based one-time passwords.
+///
+/// Cloning a `Totp` shares its replay store, so keep a clone around for enrollment
+/// after handing one to [`Fark::with_totp`](crate::Fark::with_totp).
+#[derive(Clone)]
+pub struct Totp {
+    issuer: String,
+    algorithm: TotpAlgorithm,
+    digits: u32,
+    period: u64,
+    skew: u64,
+    store: Arc<dyn TotpStore>,
+}
+
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Totp")
+            .field("issuer", &self.issuer)
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .field("skew", &self.skew)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Totp {
+    /// Defaults match what authenticator apps expect: SHA1, 6 digits, 30 second
+    /// period, and one step of clock skew either side. Accepted steps are kept in an
+    /// [`InMemoryTotpStore`]; see [`store`](Self::store) for more than one instance.
+    pub fn new(issuer: impl Into<String>) -> Self {
+        Self {
+            issuer: issuer.into(),
+            algorithm: TotpAlgorithm::Sha1,
+            digits: 6,
+            period: 30,
+            skew: 1,
+            store: Arc::new(InMemoryTotpStore::new()),
+        }
+    }
+
+    /// Records accepted time steps in `store`, e.g. one shared by all instances.
+    pub fn store<S>(mut self, store: S) -> Self
+    where
+        S: TotpStore + 'static,
+    {
+        self.store = Arc::new(store);
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: TotpAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Code length, 6 to 9 digits.
+    pub fn digits(mut self, digits: u32) -> Result<Self, ConfigError> {
+        self.digits = code_digits("totp.digits", digits)?;
+        Ok(self)
+    }
+
+    pub fn period(mut self, secs: u64) -> Self {
+        self.period = secs.max(1);
+        self
+    }
+
+    /// Number of time steps accepted before and after the current one, at most 10.
+    pub fn skew(mut self, steps: u64) -> Self {
+        self.skew = steps.min(MAX_SKEW);
+        self
+    }
+
+    /// Generates a new secret and the matching provisioning URI for `account`.
+    pub fn enroll(&self, account: &str) -> TotpEnrollment {
+        let secret = TotpSecret::generate();
+        let uri = self.provisioning_uri(account, &secret);
+        TotpEnrollment { secret, uri }
+    }
+
+    /// Builds the `otpauth://totp/...` URI understood by authenticator apps.
+    pub fn provisioning_uri(&self, account: &str, secret: &TotpSecret) -> String {
+        let issuer = utf8_percent_encode(&self.issuer, NON_ALPHANUMERIC);
+        let account = utf8_percent_encode(account, NON_ALPHANUMERIC);
+        format!(
+            "otpauth://totp/{issuer}:{account}?secret={}&issuer={issuer}&algorithm={}&digits={}&period={}",
+            secret.to_base32(),
+            self.algorithm.as_str(),
+            self.digits,
+            self.period,
+        )
+    }
+
+    /// Renders the provisioning URI as an SVG QR code.
+    #[cfg(feature = "qr")]
+    pub fn qr_svg(&self, account: &str, secret: &TotpSecret) -> Result<String, AuthError> {
+        let code = qrcode::QrCode::new(self.provisioning_uri(account, secret))
+            .map_err(AuthError::internal)?;
+        Ok(code.render::<qrcode::render::svg::Color>().build())
+    }
+
+    /// Code for the time step containing `unix_time`.
+    pub fn generate_at(&self, secret: &TotpSecret, unix_time: u64) -> String {
+        self.code_for_step(secret, unix_time / self.period)
+    }
+
+    pub fn generate(&self, secret: &TotpSecret) -> Result<String, AuthError> {
+        let current = now()?;
+        Ok(self.generate_at(secret, current))
+    }
+
+    /// Checks `code` against the skew window around `unix_time` and returns the
+    /// matching time step. Does not consult or update the replay guard.
+    pub fn verify_at(&self, secret: &TotpSecret, code: &str, unix_time: u64) -> Option<u64> {
+        let current = unix_time / self.period;
+        let first = current.saturating_sub(self.skew);
+        (first..=current.saturating_add(self.skew)).find(|step| {
+            bool::from(
+                self.code_for_step(secret, *step)
+                    .as_bytes()
+                    .ct_eq(code.as_bytes()),
+            )
+        })
+    }
+
+    /// Verifies `code` for `account` at the current time, rejecting any code from a
+    /// time step that was already accepted for that account.
+    pub async fn verify(
+        &self,
+        account: &str,
+        secret: &TotpSecret,
+        code: &str,
+    ) -> Result<(), AuthError> {
+        let current = now()?;
+        let step = self
+            .verify_at(secret, code, current)
+            .ok_or(AuthError::CodeMisMatch)?;
+        if !self.store.advance(account, step).await? {
+            return Err(AuthError::CodeMisMatch);
+        }
+        Ok(())
+    }
+
+    fn code_for_step(&self, secret: &TotpSecret, step: u64) -> String {
+        hotp_code(self.algorithm, secret, step, self.digits)
+    }
+}
@@ -0,0 +1,627 @@
+use crate::cbor::{self, Cbor};
+use crate::crypto::random_bytes;
+use crate::error::AuthError;
+use crate::strategy::BoxFuture;
+use crate::time::now;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use p256::ecdsa::signature::Verifier;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const COSE_ALG_ES256: i128 = -7;
+const COSE_ALG_RS256: i128 = -257;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Public key of a registered authenticator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CredentialPublicKey {
+    /// ECDSA P-256 with SHA-256, stored as uncompressed affine coordinates.
+    Es256 { x: Vec<u8>, y: Vec<u8> },
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+impl CredentialPublicKey {
+    fn from_cose(key: &Cbor) -> Result<Self, AuthError> {
+        let field = |label: i128| {
+            key.get_int(label)
+                .and_then(Cbor::as_bytes)
+                .map(<[u8]>::to_vec)
+                .ok_or(AuthError::CredentialRejected)
+        };
+
+        match key.get_int(3).and_then(Cbor::as_int) {
+            Some(COSE_ALG_ES256) => {
+                // kty EC2 on curve P-256
+                if key.get_int(1).and_then(Cbor::as_int) != Some(2)
+                    || key.get_int(-1).and_then(Cbor::as_int) != Some(1)
+                {
+                    return Err(AuthError::CredentialRejected);
+                }
+                Ok(CredentialPublicKey::Es256 {
+                    x: field(-2)?,
+                    y: field(-3)?,
+                })
+            }
+            Some(COSE_ALG_RS256) => {
+                if key.get_int(1).and_then(Cbor::as_int) != Some(3) {
+                    return Err(AuthError::CredentialRejected);
+                }
+                Ok(CredentialPublicKey::Rs256 {
+                    n: field(-1)?,
+                    e: field(-2)?,
+                })
+            }
+            _ => Err(AuthError::CredentialRejected),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), AuthError> {
+        match self {
+            CredentialPublicKey::Es256 { x, y } => {
+                let mut point = Vec::with_capacity(65);
+                point.push(0x04);
+                point.extend_from_slice(x);
+                point.extend_from_slice(y);
+                let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+                    .map_err(|_| AuthError::CredentialRejected)?;
+                let signature = p256::ecdsa::Signature::from_der(signature)
+                    .map_err(|_| AuthError::CredentialRejected)?;
+                key.verify(message, &signature)
+                    .map_err(|_| AuthError::CredentialRejected)
+            }
+            CredentialPublicKey::Rs256 { n, e } => {
+                let key = rsa::RsaPublicKey::new(
+                    rsa::BigUint::from_bytes_be(n),
+                    rsa::BigUint::from_bytes_be(e),
+                )
+                .map_err(|_| AuthError::CredentialRejected)?;
+                let key = rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key);
+                let signature = rsa::pkcs1v15::Signature::try_from(signature)
+                    .map_err(|_| AuthError::CredentialRejected)?;
+                key.verify(message, &signature)
+                    .map_err(|_| AuthError::CredentialRejected)
+            }
+        }
+    }
+}
+
+/// A registered authenticator, as persisted by a [`CredentialStore`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebAuthnCredential {
+    pub credential_id: Vec<u8>,
+    pub user_id: String,
+    pub public_key: CredentialPublicKey,
+    pub sign_count: u32,
+}
+
+/// Persists registered WebAuthn credentials.
+pub trait CredentialStore: Send + Sync {
+    fn save(&self, credential: WebAuthnCredential) -> BoxFuture<'_, Result<(), AuthError>>;
+
+    fn find(
+        &self,
+        credential_id: &[u8],
+    ) -> BoxFuture<'_, Result<Option<WebAuthnCredential>, AuthError>>;
+
+    fn for_user(&self, user_id: &str) -> BoxFuture<'_, Result<Vec<WebAuthnCredential>, AuthError>>;
+
+    fn update_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> BoxFuture<'_, Result<(), AuthError>>;
+}
+
+/// Process-local [`CredentialStore`], for tests and single-instance deployments.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryCredentialStore {
+    credentials: Arc<Mutex<HashMap<Vec<u8>, WebAuthnCredential>>>,
+}
+
+impl InMemoryCredentialStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CredentialStore for InMemoryCredentialStore {
+    fn save(&self, credential: WebAuthnCredential) -> BoxFuture<'_, Result<(), AuthError>> {
+        let saved = self
+            .credentials
+            .lock()
+            .map(|mut credentials| {
+                credentials.insert(credential.credential_id.clone(), credential);
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { saved })
+    }
+
+    fn find(
+        &self,
+        credential_id: &[u8],
+    ) -> BoxFuture<'_, Result<Option<WebAuthnCredential>, AuthError>> {
+        let found = self
+            .credentials
+            .lock()
+            .map(|credentials| credentials.get(credential_id).cloned())
+            .map_err(AuthError::from);
+        Box::pin(async move { found })
+    }
+
+    fn for_user(&self, user_id: &str) -> BoxFuture<'_, Result<Vec<WebAuthnCredential>, AuthError>> {
+        let found = self
+            .credentials
+            .lock()
+            .map(|credentials| {
+                credentials
+                    .values()
+                    .filter(|c| c.user_id == user_id)
+                    .cloned()
+                    .collect()
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { found })
+    }
+
+    fn update_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> BoxFuture<'_, Result<(), AuthError>> {
+        let updated = self
+            .credentials
+            .lock()
+            .map(|mut credentials| {
+                if let Some(credential) = credentials.get_mut(credential_id) {
+                    credential.sign_count = sign_count;
+                }
+            })
+            .map_err(AuthError::from);
+        Box::pin(async move { updated })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialParameter {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub alg: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+}
+
+/// `PublicKeyCredentialCreationOptions` for `navigator.credentials.create()`.
+/// Binary fields are base64url encoded.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationChallenge {
+    pub challenge: String,
+    pub rp: RelyingParty,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameter>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub attestation: &'static str,
+}
+
+/// `PublicKeyCredentialRequestOptions` for `navigator.credentials.get()`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationChallenge {
+    pub challenge: String,
+    pub rp_id: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub timeout: u64,
+    pub user_verification: &'static str,
+}
+
+/// The browser's answer to a [`RegistrationChallenge`], fields base64url encoded.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationResponse {
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ceremony {
+    Create,
+    Get,
+}
+
+impl Ceremony {
+    fn client_data_type(&self) -> &'static str {
+        match self {
+            Ceremony::Create => "webauthn.create",
+            Ceremony::Get => "webauthn.get",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PendingChallenge {
+    ceremony: Ceremony,
+    user_id: Option<String>,
+    expires_at: u64,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    attested: Option<(Vec<u8>, Cbor)>,
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>, AuthError> {
+    if data.len() < 37 {
+        return Err(AuthError::CredentialRejected);
+    }
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let attested = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // aaguid (16) | credential id length (2) | credential id | COSE key
+        let rest = data.get(37 + 16..).ok_or(AuthError::CredentialRejected)?;
+        if rest.len() < 2 {
+            return Err(AuthError::CredentialRejected);
+        }
+        let id_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let id = rest
+            .get(2..2 + id_len)
+            .ok_or(AuthError::CredentialRejected)?
+            .to_vec();
+        let (key, _) = cbor::decode(&rest[2 + id_len..]).ok_or(AuthError::CredentialRejected)?;
+        Some((id, key))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: &data[..32],
+        flags,
+        sign_count,
+        attested,
+    })
+}
+
+fn decode_b64(value: &str) -> Result<Vec<u8>, AuthError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| AuthError::InvalidInput)
+}
+
+/// WebAuthn relying party: issues challenges and verifies authenticator responses.
+///
+/// Cloning shares the pending challenges and credential store, so the clone passed
+/// to [`Fark::with_webauthn`](crate::Fark::with_webauthn) sees challenges issued by
+/// the one kept for the registration and login endpoints.
+#[derive(Clone)]
+pub struct WebAuthn {
+    rp_id: String,
+    rp_name: String,
+    origins: Vec<String>,
+    challenge_ttl: u64,
+    require_user_verification: bool,
+    store: Arc<dyn CredentialStore>,
+    pending: Arc<Mutex<HashMap<String, PendingChallenge>>>,
+}
+
+impl WebAuthn {
+    pub fn new<S>(rp_id: impl Into<String>, origin: impl Into<String>, store: S) -> Self
+    where
+        S: CredentialStore + 'static,
+    {
+        let rp_id = rp_id.into();
+        Self {
+            rp_name: rp_id.clone(),
+            rp_id,
+            origins: vec![origin.into()],
+            challenge_ttl: 300,
+            require_user_verification: false,
+            store: Arc::new(store),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn rp_name(mut self, name: impl Into<String>) -> Self {
+        self.rp_name = name.into();
+        self
+    }
+
+    /// Accepts responses from an additional origin, e.g. a second subdomain.
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origins.push(origin.into());
+        self
+    }
+
+    pub fn challenge_ttl(mut self, secs: u64) -> Self {
+        self.challenge_ttl = secs;
+        self
+    }
+
+    /// Requires the authenticator to verify the user (PIN, biometrics), not just presence.
+    pub fn require_user_verification(mut self, required: bool) -> Self {
+        self.require_user_verification = required;
+        self
+    }
+
+    pub async fn start_registration(
+        &self,
+        user_id: &str,
+        user_name: &str,
+    ) -> Result<RegistrationChallenge, AuthError> {
+        let existing = self.store.for_user(user_id).await?;
+        let challenge = self.new_challenge(Ceremony::Create, Some(user_id))?;
+
+        Ok(RegistrationChallenge {
+            challenge,
+            rp: RelyingParty {
+                id: self.rp_id.clone(),
+                name: self.rp_name.clone(),
+            },
+            user: UserEntity {
+                id: URL_SAFE_NO_PAD.encode(user_id),
+                name: user_name.to_string(),
+                display_name: user_name.to_string(),
+            },
+            pub_key_cred_params: vec![
+                CredentialParameter {
+                    kind: "public-key",
+                    alg: COSE_ALG_ES256 as i64,
+                },
+                CredentialParameter {
+                    kind: "public-key",
+                    alg: COSE_ALG_RS256 as i64,
+                },
+            ],
+            timeout: self.timeout_ms()?,
+            exclude_credentials: existing.iter().map(descriptor).collect(),
+            attestation: "none",
+        })
+    }
+
+    /// Verifies the attestation and stores the new credential.
+    pub async fn finish_registration(
+        &self,
+        response: &RegistrationResponse,
+    ) -> Result<WebAuthnCredential, AuthError> {
+        let client_data_json = decode_b64(&response.client_data_json)?;
+        let pending = self.check_client_data(&client_data_json, Ceremony::Create)?;
+        let user_id = pending.user_id.ok_or(AuthError::CredentialRejected)?;
+
+        let attestation_object = decode_b64(&response.attestation_object)?;
+        let (attestation, _) =
+            cbor::decode(&attestation_object).ok_or(AuthError::CredentialRejected)?;
+        let auth_data_bytes = attestation
+            .get_text("authData")
+            .and_then(Cbor::as_bytes)
+            .ok_or(AuthError::CredentialRejected)?;
+        let auth_data = parse_authenticator_data(auth_data_bytes)?;
+        self.check_flags(&auth_data)?;
+
+        let (credential_id, cose_key) = auth_data
+            .attested
+            .as_ref()
+            .ok_or(AuthError::CredentialRejected)?;
+        let public_key = CredentialPublicKey::from_cose(cose_key)?;
+
+        match attestation.get_text("fmt").and_then(Cbor::as_text) {
+            Some("none") => {}
+            Some("packed") => {
+                let statement = attestation
+                    .get_text("attStmt")
+                    .ok_or(AuthError::CredentialRejected)?;
+                // Only self attestation is supported; certificate chains need a trust store
+                if statement.get_text("x5c").is_some() {
+                    return Err(AuthError::CredentialRejected);
+                }
+                let signature = statement
+                    .get_text("sig")
+                    .and_then(Cbor::as_bytes)
+                    .ok_or(AuthError::CredentialRejected)?;
+                let mut signed = auth_data_bytes.to_vec();
+                signed.extend_from_slice(&Sha256::digest(&client_data_json));
+                public_key.verify(&signed, signature)?;
+            }
+            _ => return Err(AuthError::CredentialRejected),
+        }
+
+        if self.store.find(credential_id).await?.is_some() {
+            return Err(AuthError::CredentialRejected);
+        }
+
+        let credential = WebAuthnCredential {
+            credential_id: credential_id.clone(),
+            user_id,
+            public_key,
+            sign_count: auth_data.sign_count,
+        };
+        self.store.save(credential.clone()).await?;
+        Ok(credential)
+    }
+
+    /// Issues an assertion challenge. With `user_id` the browser is told which
+    /// credentials to use; without it discoverable credentials (passkeys) are used.
+    pub async fn start_authentication(
+        &self,
+        user_id: Option<&str>,
+    ) -> Result<AuthenticationChallenge, AuthError> {
+        let allow_credentials = match user_id {
+            Some(user_id) => self
+                .store
+                .for_user(user_id)
+                .await?
+                .iter()
+                .map(descriptor)
+                .collect(),
+            None => Vec::new(),
+        };
+        let challenge = self.new_challenge(Ceremony::Get, user_id)?;
+
+        Ok(AuthenticationChallenge {
+            challenge,
+            rp_id: self.rp_id.clone(),
+            allow_credentials,
+            timeout: self.timeout_ms()?,
+            user_verification: if self.require_user_verification {
+                "required"
+            } else {
+                "preferred"
+            },
+        })
+    }
+
+    /// Verifies an assertion and returns the user id the credential belongs to.
+    pub async fn finish_authentication(
+        &self,
+        credential_id: &str,
+        client_data_json: &str,
+        authenticator_data: &str,
+        signature: &str,
+    ) -> Result<String, AuthError> {
+        let credential_id = decode_b64(credential_id)?;
+        let client_data_json = decode_b64(client_data_json)?;
+        let auth_data_bytes = decode_b64(authenticator_data)?;
+        let signature = decode_b64(signature)?;
+
+        let pending = self.check_client_data(&client_data_json, Ceremony::Get)?;
+        let credential = self
+            .store
+            .find(&credential_id)
+            .await?
+            .ok_or(AuthError::CredentialRejected)?;
+        if pending
+            .user_id
+            .is_some_and(|user_id| user_id != credential.user_id)
+        {
+            return Err(AuthError::CredentialRejected);
+        }
+
+        let auth_data = parse_authenticator_data(&auth_data_bytes)?;
+        self.check_flags(&auth_data)?;
+
+        let mut signed = auth_data_bytes.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+        credential.public_key.verify(&signed, &signature)?;
+
+        // A counter that fails to increase suggests a cloned authenticator
+        if (auth_data.sign_count != 0 || credential.sign_count != 0)
+            && auth_data.sign_count <= credential.sign_count
+        {
+            return Err(AuthError::CredentialRejected);
+        }
+        self.store
+            .update_sign_count(&credential_id, auth_data.sign_count)
+            .await?;
+
+        Ok(credential.user_id)
+    }
+
+    /// The challenge lifetime in milliseconds, as `timeout` in the ceremony options.
+    fn timeout_ms(&self) -> Result<u64, AuthError> {
+        self.challenge_ttl
+            .checked_mul(1000)
+            .ok_or_else(|| AuthError::internal("webauthn challenge_ttl is out of range"))
+    }
+
+    fn new_challenge(
+        &self,
+        ceremony: Ceremony,
+        user_id: Option<&str>,
+    ) -> Result<String, AuthError> {
+        let issued_at = now()?;
+        let challenge = URL_SAFE_NO_PAD.encode(random_bytes(32));
+
+        let mut pending = self.pending.lock()?;
+        pending.retain(|_, p| p.expires_at > issued_at);
+        pending.insert(
+            challenge.clone(),
+            PendingChallenge {
+                ceremony,
+                user_id: user_id.map(str::to_string),
+                expires_at: issued_at
+                    .checked_add(self.challenge_ttl)
+                    .ok_or_else(|| AuthError::internal("webauthn challenge_ttl is out of range"))?,
+            },
+        );
+        Ok(challenge)
+    }
+
+    /// Checks type and origin, then consumes the challenge the client signed.
+    fn check_client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony: Ceremony,
+    ) -> Result<PendingChallenge, AuthError> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).map_err(|_| AuthError::InvalidInput)?;
+        if client_data.kind != ceremony.client_data_type()
+            || !self.origins.contains(&client_data.origin)
+        {
+            return Err(AuthError::CredentialRejected);
+        }
+
+        let current = now()?;
+        let pending = self
+            .pending
+            .lock()?
+            .remove(&client_data.challenge)
+            .ok_or(AuthError::CredentialRejected)?;
+        if pending.ceremony != ceremony || pending.expires_at <= current {
+            return Err(AuthError::CredentialRejected);
+        }
+        Ok(pending)
+    }
+
+    fn check_flags(&self, auth_data: &AuthenticatorData<'_>) -> Result<(), AuthError> {
+        let expected_rp_hash = Sha256::digest(self.rp_id.as_bytes());
+        if auth_data.rp_id_hash != expected_rp_hash.as_slice()
+            || auth_data.flags & FLAG_USER_PRESENT == 0
+            || (self.require_user_verification && auth_data.flags & FLAG_USER_VERIFIED == 0)
+        {
+            return Err(AuthError::CredentialRejected);
+        }
+        Ok(())
+    }
+}
+
+fn descriptor(credential: &WebAuthnCredential) -> CredentialDescriptor {
+    CredentialDescriptor {
+        kind: "public-key",
+        id: URL_SAFE_NO_PAD.encode(&credential.credential_id),
+    }
+}